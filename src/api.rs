@@ -0,0 +1,576 @@
+use crate::app::{Actor, App, Task, TaskEvent, TaskId, TaskStatus, Transition};
+use crate::config::Config;
+use crate::lab::Preset;
+use crate::oauth::OAuth;
+use crate::report::TestResult;
+use crate::{with_anyhow, AnyHowError};
+use anyhow::anyhow;
+use bytes::BufMut;
+use futures::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::from_slice;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::spawn;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use warp::http::StatusCode;
+use warp::multipart::FormData;
+use warp::sse::{self, Event};
+use warp::{reply, Filter, Rejection, Reply};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub lab: String,
+    pub preset: String,
+    pub status: TaskStatus,
+    pub retry: u32,
+    pub exit_status: Option<i32>,
+    pub submit_time: Option<u64>, // in second since epoch
+    pub start_time: Option<u64>,
+    pub finish_time: Option<u64>,
+    pub results: Vec<TestResult>, // empty if not finished or output has no test
+    pub transitions: Vec<Transition>,
+    pub position: Option<usize>, // tasks to be dispatched before, only for pending task
+    pub wait_time: Option<u64>,  // maximum, in second, only for pending task
+    pub expected_wait_time: Option<u64>, // by recent run time, in second, only for pending task
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Submitted {
+    pub id: TaskId,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Error {
+    pub error: String,
+}
+
+async fn collect_form(form: FormData) -> anyhow::Result<HashMap<String, Vec<u8>>> {
+    Ok(form
+        .and_then(|part| {
+            let name = part.name().to_string();
+            part.stream()
+                .try_fold(Vec::new(), |mut data, chunk| {
+                    data.put(chunk);
+                    async { Ok(data) }
+                })
+                .map_ok(|data| (name, data))
+        })
+        .try_collect()
+        .await?)
+}
+
+fn take_upload(form: &mut HashMap<String, Vec<u8>>) -> anyhow::Result<Vec<u8>> {
+    let upload = form
+        .remove("upload")
+        .ok_or(anyhow!("no upload in submission"))?;
+    if upload.is_empty() {
+        return Err(anyhow!("submission is empty"));
+    }
+    Ok(upload)
+}
+
+async fn task_info(app: &App<Preset>, task_id: TaskId) -> anyhow::Result<TaskInfo> {
+    let task = app.get_task(task_id).await?;
+    let pending = task.status == TaskStatus::Pending;
+    Ok(TaskInfo {
+        id: task_id,
+        lab: task.preset.lab().to_string(),
+        preset: task.preset.to_string(),
+        status: task.status,
+        retry: task.retry,
+        exit_status: task.exit_status,
+        submit_time: task.submit_time(),
+        start_time: task.start_time(),
+        finish_time: task.finish_time(),
+        results: task.results,
+        transitions: task.transitions,
+        position: if pending {
+            app.get_position(task_id).await
+        } else {
+            None
+        },
+        wait_time: if pending {
+            Some(app.get_wait_time(task_id).await.as_secs())
+        } else {
+            None
+        },
+        expected_wait_time: if pending {
+            Some(app.get_expected_wait_time(task_id).await.as_secs())
+        } else {
+            None
+        },
+    })
+}
+
+fn is_done(status: TaskStatus) -> bool {
+    status == TaskStatus::Finished || status == TaskStatus::Canceled
+}
+
+fn status_event(info: &TaskInfo) -> Event {
+    Event::default().event("status").json_data(info).unwrap()
+}
+
+fn output_event(output: &[u8]) -> Event {
+    Event::default()
+        .event("output")
+        .json_data(String::from_utf8_lossy(output))
+        .unwrap()
+}
+
+// a "status" event with `TaskInfo` whenever it changes, and "output" events
+// with JSON string of output text, until the task is finished or canceled
+fn task_events(
+    app: Arc<App<Preset>>,
+    task_id: TaskId,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let (event_tx, event_rx) = mpsc::channel(16);
+    spawn(async move {
+        // error if client is gone
+        let _ = forward_events(app, task_id, event_tx).await;
+    });
+    stream::unfold(event_rx, |mut event_rx| async move {
+        let event = event_rx.recv().await?;
+        Some((Ok(event), event_rx))
+    })
+}
+
+async fn forward_events(
+    app: Arc<App<Preset>>,
+    task_id: TaskId,
+    event_tx: mpsc::Sender<Event>,
+) -> anyhow::Result<()> {
+    let mut receiver = app.subscribe();
+    let mut info = task_info(&app, task_id).await?;
+    event_tx.send(status_event(&info)).await?;
+    if is_done(info.status) {
+        return Ok(());
+    }
+    let mut sent_length = 0; // output of current run
+    if info.status == TaskStatus::Running {
+        let output = app.get_output(task_id).await?;
+        sent_length = output.len();
+        event_tx.send(output_event(&output)).await?;
+    }
+    loop {
+        // end on the event instead of observed status, so output sent right
+        // before finishing is not dropped
+        let mut done = false;
+        match receiver.recv().await {
+            Ok(TaskEvent::Output(id, offset, output)) if id == task_id => {
+                let end = offset + output.len();
+                if end > sent_length {
+                    let skip = sent_length.saturating_sub(offset);
+                    event_tx
+                        .send(output_event(&output.as_bytes()[skip..]))
+                        .await?;
+                    sent_length = end;
+                }
+                continue;
+            }
+            // a requeued task runs from start again
+            Ok(TaskEvent::Status(id, TaskStatus::Running))
+                if id == task_id && info.status != TaskStatus::Running =>
+            {
+                sent_length = 0
+            }
+            Ok(TaskEvent::Status(id, status)) if id == task_id => done = is_done(status),
+            Ok(TaskEvent::Queue) => {}
+            // output and status of other tasks, their effect on the queue
+            // comes with a queue event
+            Ok(_) => continue,
+            // anything may change after missing some events, catch up output from store
+            Err(RecvError::Lagged(_)) => {
+                if info.status == TaskStatus::Running {
+                    let output = app.get_output(task_id).await?;
+                    if output.len() > sent_length {
+                        event_tx.send(output_event(&output[sent_length..])).await?;
+                        sent_length = output.len();
+                    }
+                }
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        }
+        let new_info = task_info(&app, task_id).await?;
+        if done || new_info != info {
+            event_tx.send(status_event(&new_info)).await?;
+            info = new_info;
+        }
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+// JSON version of the pages under /task, mounted at /api/v1
+pub fn routes(
+    app: Arc<App<Preset>>,
+    oauth: Arc<OAuth>,
+    config: &Config,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let upload_limit = config.upload_limit;
+
+    let list_app = app.clone();
+    let route = oauth
+        .user_id()
+        .and(warp::path!("tasks"))
+        .and(warp::get())
+        .and_then(move |user_id: String| {
+            let list_app = list_app.clone();
+            with_anyhow(async move {
+                let task_list = list_app
+                    .data
+                    .read()
+                    .await
+                    .user_table
+                    .get(&user_id)
+                    .cloned()
+                    .unwrap_or_default();
+                let mut info_list = Vec::new();
+                for task_id in task_list {
+                    // skip unparseable record, e.g., of a removed lab
+                    if let Ok(info) = task_info(&list_app, task_id).await {
+                        info_list.push(info);
+                    }
+                }
+                Ok(reply::json(&info_list))
+            })
+        });
+
+    let submit_app = app.clone();
+    let submit_labs = config.labs.clone();
+    let route = route.or(warp::path!("tasks")
+        .and(warp::post())
+        .and(oauth.student_id())
+        .and(warp::multipart::form().max_length(upload_limit))
+        .and_then(move |user_id, form: FormData| {
+            let submit_app = submit_app.clone();
+            let submit_labs = submit_labs.clone();
+            with_anyhow(async move {
+                let mut form = collect_form(form).await?;
+                let preset: HashMap<String, String> = from_slice(
+                    &form
+                        .remove("preset")
+                        .ok_or(anyhow!("no preset in submission"))?,
+                )?;
+                let preset: Preset = preset.try_into()?;
+                if !submit_labs.iter().any(|lab| lab == preset.lab()) {
+                    return Err(anyhow!("{} is not open for submission", preset.lab()));
+                }
+                let upload = take_upload(&mut form)?;
+                let task_id = submit_app
+                    .push_task(Task {
+                        user_id,
+                        preset,
+                        upload,
+                        status: TaskStatus::Pending,
+                        retry: 0,
+                        exit_status: None,
+                        results: Vec::new(),
+                        transitions: Vec::new(), // set on pushing
+                    })
+                    .await?;
+                Ok(reply::with_status(
+                    reply::json(&Submitted { id: task_id }),
+                    StatusCode::CREATED,
+                ))
+            })
+        }));
+
+    let task_app = app.clone();
+    let route = route.or(oauth
+        .user_id()
+        .and(warp::path!("tasks" / TaskId))
+        .and(warp::get())
+        .and_then(move |user_id: String, task_id| {
+            let task_app = task_app.clone();
+            with_anyhow(async move {
+                if !task_app.allow_access(&user_id, task_id).await {
+                    return Err(anyhow!("task id not accessible"));
+                }
+                Ok(reply::json(&task_info(&task_app, task_id).await?))
+            })
+        }));
+
+    let replace_app = app.clone();
+    let route = route.or(oauth
+        .user_id()
+        .and(warp::path!("tasks" / TaskId / "upload"))
+        .and(warp::put())
+        .and(warp::multipart::form().max_length(upload_limit))
+        .and_then(move |user_id: String, task_id, form: FormData| {
+            let replace_app = replace_app.clone();
+            with_anyhow(async move {
+                let upload = take_upload(&mut collect_form(form).await?)?;
+                if !replace_app.allow_access(&user_id, task_id).await {
+                    return Err(anyhow!("update upload reject"));
+                }
+                replace_app.replace_upload(task_id, upload).await?;
+                Ok(reply::json(&task_info(&replace_app, task_id).await?))
+            })
+        }));
+
+    let cancel_app = app.clone();
+    let route = route.or(oauth
+        .user_id()
+        .and(warp::path!("tasks" / TaskId / "cancel"))
+        .and(warp::post())
+        .and_then(move |user_id: String, task_id| {
+            let cancel_app = cancel_app.clone();
+            with_anyhow(async move {
+                if !cancel_app.allow_access(&user_id, task_id).await {
+                    return Err(anyhow!("cancel rejected"));
+                }
+                cancel_app
+                    .cancel_task(task_id, Actor::User(user_id))
+                    .await?;
+                Ok(reply::json(&task_info(&cancel_app, task_id).await?))
+            })
+        }));
+
+    let events_app = app.clone();
+    let route = route.or(oauth
+        .user_id()
+        .and(warp::path!("tasks" / TaskId / "events"))
+        .and(warp::get())
+        .and_then(move |user_id: String, task_id| {
+            let events_app = events_app.clone();
+            with_anyhow(async move {
+                if !events_app.allow_access(&user_id, task_id).await {
+                    return Err(anyhow!("task id not accessible"));
+                }
+                Ok(sse::reply(
+                    sse::keep_alive().stream(task_events(events_app, task_id)),
+                ))
+            })
+        }));
+
+    let output_app = app;
+    let route = route.or(oauth
+        .user_id()
+        .and(warp::path!("tasks" / TaskId / "output"))
+        .and(warp::get())
+        .and_then(move |user_id: String, task_id| {
+            let output_app = output_app.clone();
+            with_anyhow(async move {
+                if !output_app.allow_access(&user_id, task_id).await {
+                    return Err(anyhow!("task id not accessible"));
+                }
+                if output_app.get_task(task_id).await?.status == TaskStatus::Pending {
+                    return Err(anyhow!("no available output"));
+                }
+                Ok(reply::with_header(
+                    output_app.get_output(task_id).await?,
+                    "Content-Type",
+                    "text/plain; charset=utf-8",
+                ))
+            })
+        }));
+
+    warp::path!("api" / "v1" / ..).and(route.recover(recover))
+}
+
+async fn recover(rejection: Rejection) -> Result<impl Reply, Infallible> {
+    let (status, error) = if OAuth::is_unauthorized(&rejection) {
+        (StatusCode::UNAUTHORIZED, "login required".to_string())
+    } else if OAuth::is_not_enrolled(&rejection) {
+        (StatusCode::FORBIDDEN, "not enrolled".to_string())
+    } else if let Some(AnyHowError(error)) = rejection.find() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "not found".to_string())
+    } else {
+        (StatusCode::BAD_REQUEST, format!("{:?}", rejection))
+    };
+    Ok(reply::with_status(reply::json(&Error { error }), status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::data;
+    use crate::store::MemoryStore;
+    use serde_json::{from_slice, json, to_vec};
+    use std::path::Path;
+    use warp::http::Response;
+    use warp::hyper::body::Bytes;
+
+    const BOUNDARY: &str = "cs5223fet-test-boundary";
+
+    // routes with API tokens of alice and bob, and only `students` may submit
+    // if not empty
+    async fn new_routes(
+        students: &[&str],
+    ) -> (
+        impl Filter<Extract = impl Reply, Error = Rejection> + Clone,
+        String,
+        String,
+    ) {
+        data::load(Path::new("labs")).unwrap();
+        let config = Config {
+            url: String::from("http://localhost"),
+            worker_secret: String::from("secret"),
+            students: students.iter().map(|login| login.to_string()).collect(),
+            ..Config::default()
+        };
+        let store = Arc::new(MemoryStore::default());
+        let app = Arc::new(App::new(store.clone(), &config).await.unwrap());
+        let oauth = Arc::new(OAuth::new(&config, store).await.unwrap());
+        let alice = oauth.create_token("alice", "test").await.unwrap();
+        let bob = oauth.create_token("bob", "test").await.unwrap();
+        (routes(app, oauth, &config), alice, bob)
+    }
+
+    fn multipart(field_list: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, value) in field_list {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                    BOUNDARY, name
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(value);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn submission(lab: &str, test: &str) -> Vec<u8> {
+        let preset = json!({
+            ":lab": lab,
+            ":test": test,
+            ":log_level": "disable",
+            ":check": "no",
+        });
+        multipart(&[("preset", &to_vec(&preset).unwrap()), ("upload", b"upload")])
+    }
+
+    async fn request(
+        route: &(impl Filter<Extract = impl Reply, Error = Rejection> + Clone + 'static),
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Vec<u8>>,
+    ) -> Response<Bytes> {
+        let mut request = warp::test::request().method(method).path(path);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        if let Some(body) = body {
+            request = request
+                .header(
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                )
+                .body(body);
+        }
+        request.reply(route).await
+    }
+
+    fn error_of(response: &Response<Bytes>) -> String {
+        from_slice::<Error>(response.body()).unwrap().error
+    }
+
+    #[tokio::test]
+    async fn submit_list_and_get() {
+        let (route, alice, bob) = new_routes(&[]).await;
+        let response = request(&route, "GET", "/api/v1/tasks", Some(&alice), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(from_slice::<Vec<TaskInfo>>(response.body()).unwrap(), []);
+
+        let body = submission("lab4", "[1,1]");
+        let response = request(&route, "POST", "/api/v1/tasks", Some(&alice), Some(body)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let task_id = from_slice::<Submitted>(response.body()).unwrap().id;
+
+        let response = request(&route, "GET", "/api/v1/tasks", Some(&alice), None).await;
+        let info_list: Vec<TaskInfo> = from_slice(response.body()).unwrap();
+        assert_eq!(info_list.len(), 1);
+        let info = &info_list[0];
+        assert_eq!(info.id, task_id);
+        assert_eq!(info.lab, "lab4");
+        assert_eq!(
+            info.preset,
+            "Lab 4: TEST 1: Commands return OK (5pts), log level: Disable"
+        );
+        assert_eq!(info.status, TaskStatus::Pending);
+        assert_eq!(info.position, Some(0));
+        assert!(info.submit_time.is_some() && info.start_time.is_none());
+
+        let path = format!("/api/v1/tasks/{}", task_id);
+        let response = request(&route, "GET", &path, Some(&alice), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(&from_slice::<TaskInfo>(response.body()).unwrap(), info);
+        let response = request(&route, "GET", "/api/v1/tasks", Some(&bob), None).await;
+        assert_eq!(from_slice::<Vec<TaskInfo>>(response.body()).unwrap(), []);
+
+        let path = format!("/api/v1/tasks/{}/cancel", task_id);
+        let response = request(&route, "POST", &path, Some(&alice), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let info: TaskInfo = from_slice(response.body()).unwrap();
+        assert_eq!(info.status, TaskStatus::Canceled);
+        assert_eq!(info.position, None);
+    }
+
+    #[tokio::test]
+    async fn error_json() {
+        let (route, alice, bob) = new_routes(&[]).await;
+        let body = submission("lab4", "[1,1]");
+        let response = request(&route, "POST", "/api/v1/tasks", Some(&alice), Some(body)).await;
+        let task_id = from_slice::<Submitted>(response.body()).unwrap().id;
+
+        let response = request(&route, "GET", "/api/v1/tasks", None, None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(error_of(&response), "login required");
+        let response = request(&route, "GET", "/api/v1/tasks", Some("forged"), None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let path = format!("/api/v1/tasks/{}", task_id);
+        let response = request(&route, "GET", &path, Some(&bob), None).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error_of(&response), "task id not accessible");
+
+        for (lab, test, error) in [
+            ("lab3", "[1,1]", "lab3 is not open for submission"),
+            ("lab4", "[1,9]", "invalid part and test combination"),
+        ] {
+            let body = submission(lab, test);
+            let response = request(&route, "POST", "/api/v1/tasks", Some(&bob), Some(body)).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(error_of(&response), error);
+        }
+        let body = multipart(&[("upload", b"upload")]);
+        let response = request(&route, "POST", "/api/v1/tasks", Some(&bob), Some(body)).await;
+        assert_eq!(error_of(&response), "no preset in submission");
+
+        let response = request(&route, "GET", "/api/v1/nothing", Some(&alice), None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_of(&response), "not found");
+    }
+
+    #[tokio::test]
+    async fn not_enrolled_only_on_submit() {
+        let (route, alice, bob) = new_routes(&["alice"]).await;
+        let body = submission("lab4", "[1,1]");
+        let response = request(&route, "POST", "/api/v1/tasks", Some(&bob), Some(body)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(error_of(&response), "not enrolled");
+
+        let response = request(&route, "GET", "/api/v1/tasks", Some(&bob), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = request(&route, "GET", "/api/v1/tasks/1", Some(&bob), None).await;
+        assert_eq!(error_of(&response), "task id not accessible");
+        let response = request(&route, "GET", "/api/v1/nothing", Some(&bob), None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = submission("lab4", "[1,1]");
+        let response = request(&route, "POST", "/api/v1/tasks", Some(&alice), Some(body)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+}
@@ -1,48 +1,125 @@
+use crate::config::Config;
 use crate::preset::Preset;
+use crate::protocol::{self, FromWorker, ToWorker};
+use crate::queue::{Policy, RunTime, TaskQueue, Usage};
+use crate::report::{self, TestResult};
+use crate::store::TaskStore;
 use anyhow::anyhow;
 use futures::prelude::*;
-use redis::{AsyncCommands, Client};
 use rmp_serde::{from_slice, to_vec_named};
-use serde::Deserialize as Deser;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{from_str, to_string};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{self, Display, Formatter};
-use std::mem::take;
+use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::fs;
-use tokio::sync::{mpsc, Mutex, RwLock};
-use tokio::time::{sleep, Instant};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, mpsc, Mutex, RwLock};
+use tokio::time::{sleep, timeout, Instant};
 use tokio::{select, spawn};
 use warp::ws::{Message, WebSocket};
 
+pub type WorkerId = u32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppStatus {
+    pub workers: BTreeMap<WorkerId, Option<TaskId>>, // running task of each connected worker
+    pub queue: TaskQueue,
+    pub policy: Policy,
+    pub usage: Usage,
+    pub run_time: RunTime,
+    pub mode: DispatchMode,
+    pub last_id: TaskId,
+    next_worker: WorkerId,
+}
+
+// set by staff, e.g., around maintenance
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum AppStatus {
-    Disconnected(u32, u32), // next pending task id, last task id
-    Running(u32, u32),      // running task id, last task id
-    StandBy(u32),           // last task id
+pub enum DispatchMode {
+    Open,
+    Paused,   // accept submissions, but dispatch nothing
+    Draining, // dispatch nothing, and become paused once running tasks finish
+    Closed,   // reject submissions, and keep dispatching queued ones
 }
 
-impl Display for AppStatus {
+impl FromStr for DispatchMode {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "open" => Ok(Self::Open),
+            "paused" => Ok(Self::Paused),
+            "draining" => Ok(Self::Draining),
+            "closed" => Ok(Self::Closed),
+            _ => Err(anyhow!("unknown dispatch mode {:?}", s)),
+        }
+    }
+}
+
+impl Display for DispatchMode {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Disconnected(pending_id, last_id) => {
-                write!(f, "disconnected, {} waiting", last_id + 1 - pending_id)
-            }
-            Self::Running(task_id, last_id) => {
-                write!(f, "running(#{}), {} waiting", task_id, last_id - task_id)
-            }
-            Self::StandBy(_) => write!(f, "free"),
+            Self::Open => write!(f, "open"),
+            Self::Paused => write!(f, "paused"),
+            Self::Draining => write!(f, "draining"),
+            Self::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+impl Display for AppStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.mode != DispatchMode::Open {
+            write!(f, "{}, ", self.mode)?;
+        }
+        if self.workers.is_empty() {
+            return write!(f, "disconnected, {} waiting", self.queue.len());
+        }
+        let running: Vec<_> = self
+            .workers
+            .values()
+            .flatten()
+            .map(|task_id| format!("#{}", task_id))
+            .collect();
+        if running.is_empty() && self.queue.is_empty() {
+            write!(f, "free, {} worker(s)", self.workers.len())
+        } else if running.is_empty() {
+            // only when not dispatching
+            write!(
+                f,
+                "idle, {} worker(s), {} waiting",
+                self.workers.len(),
+                self.queue.len()
+            )
+        } else {
+            write!(
+                f,
+                "{} worker(s), running({}), {} waiting",
+                self.workers.len(),
+                running.join(", "),
+                self.queue.len()
+            )
         }
     }
 }
 
 pub struct App<Preset> {
     pub status: RwLock<AppStatus>,
-    worker_tx: Mutex<Option<mpsc::Sender<ToWorker>>>,
+    worker_table: Mutex<HashMap<WorkerId, Worker>>,
     pub data: RwLock<AppData<Preset>>,
-    client: Client,
+    store: Arc<dyn TaskStore>,
+    events: broadcast::Sender<TaskEvent>,
+    ping_interval: Duration,
+    grace_period: Duration,
+    retry_limit: u32,
+    worker_secret: String,
+}
+
+// connected worker as it introduced itself in hello
+struct Worker {
+    // unbounded, so sending never waits for the worker while holding locks
+    sender: mpsc::UnboundedSender<ToWorker>,
+    name: String,
+    capabilities: Vec<String>,
 }
 
 pub struct AppData<Preset> {
@@ -57,103 +134,113 @@ pub struct Task<Preset> {
     pub preset: Preset,
     pub upload: Vec<u8>,
     pub status: TaskStatus,
+    pub retry: u32,                   // times requeued because worker disconnected
+    pub exit_status: Option<i32>,     // of test command, `None` if not finished or killed by signal
+    pub results: Vec<TestResult>,     // parsed from output when finished
+    pub transitions: Vec<Transition>, // empty for tasks before recorded
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-pub enum TaskStatus {
-    Pending,
-    Running,
-    Finished,
-    Canceled,
+// every status a task entered, and who made it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    pub time: u64, // in second since epoch
+    pub status: TaskStatus,
+    pub actor: Actor,
 }
 
-#[derive(Debug, Serialize)]
-struct ToWorker {
-    task_id: TaskId,
-    command: String,
-    upload: Vec<u8>,
-    timeout: u64, // in second
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Actor {
+    User(String),
+    Admin(String),
+    Worker(String), // name in hello
+    System,         // requeue and cancel on worker disconnecting or restart
 }
 
-#[derive(Debug, Deserialize)]
-struct FromWorker {
-    task_id: TaskId,
-    output: String,
-    // anything else?
+impl Display for Actor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User(user_id) => write!(f, "user {}", user_id),
+            Self::Admin(user_id) => write!(f, "admin {}", user_id),
+            Self::Worker(name) => write!(f, "worker {}", name),
+            Self::System => write!(f, "system"),
+        }
+    }
 }
 
-impl<P> App<P> {
-    pub async fn new() -> anyhow::Result<Self> {
-        let client = Client::open("redis://localhost")?;
-        let mut conn = client.get_async_connection().await?;
-        let mut last_id = 0;
-        let mut user_table: HashMap<_, Vec<_>> = HashMap::new();
-        loop {
-            let mut query: HashMap<String, String> =
-                conn.hgetall(format!("task:{}", last_id + 1)).await?;
-            if query.is_empty() {
-                break;
-            }
-            last_id += 1;
-            user_table
-                .entry(query.remove("user-id").unwrap())
-                .or_default()
-                .push(last_id);
-
-            let status: TaskStatus = from_str(query.get("status").unwrap()).unwrap();
-            if status != TaskStatus::Finished {
-                // println!("[app] Cancel task #{}", last_id);
-                conn.hset(
-                    format!("task:{}", last_id),
-                    "status",
-                    to_string(&TaskStatus::Canceled).unwrap(),
-                )
-                .await?;
-            }
+impl Display for Transition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (self.status, &self.actor) {
+            (TaskStatus::Pending, Actor::User(_)) => write!(f, "submitted by {}", self.actor),
+            (TaskStatus::Pending, _) => write!(f, "requeued by {}", self.actor),
+            (TaskStatus::Running, _) => write!(f, "started on {}", self.actor),
+            (TaskStatus::Finished, _) => write!(f, "finished on {}", self.actor),
+            (TaskStatus::Canceled, _) => write!(f, "canceled by {}", self.actor),
         }
-        println!("[app] Initialized with {} past tasks", last_id);
+    }
+}
 
-        Ok(Self {
-            status: RwLock::new(AppStatus::Disconnected(last_id + 1, last_id)),
-            worker_tx: Mutex::new(None),
-            data: RwLock::new(AppData {
-                task_table: HashMap::new(),
-                user_table,
-            }),
-            client,
-        })
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn transitions_field(query: &HashMap<String, String>) -> Vec<Transition> {
+    query
+        .get("transitions")
+        .map(|transitions| from_str(transitions).unwrap())
+        .unwrap_or_default()
+}
+
+// in second since epoch, `None` if not happened yet or before recorded
+impl<P> Task<P> {
+    fn last_time(&self, entered: impl Fn(TaskStatus) -> bool) -> Option<u64> {
+        self.transitions
+            .iter()
+            .rev()
+            .find(|transition| entered(transition.status))
+            .map(|transition| transition.time)
     }
 
-    async fn disconnect_worker(&self) {
-        let mut status = self.status.write().await;
-        let mut worker_tx = self.worker_tx.lock().await;
-        *status = match *status {
-            AppStatus::Disconnected(_, _) => unreachable!(),
-            AppStatus::Running(task_id, last_id) => {
-                let mut data = self.data.write().await;
-                data.task_table.get_mut(&task_id).unwrap().status = TaskStatus::Canceled;
-                let _: () = self
-                    .client
-                    .get_async_connection()
-                    .await
-                    .unwrap()
-                    .hset(
-                        format!("task:{}", task_id),
-                        "status",
-                        to_string(&TaskStatus::Canceled).unwrap(),
-                    )
-                    .await
-                    .unwrap();
-                AppStatus::Disconnected(task_id + 1, last_id)
-            }
-            AppStatus::StandBy(last_id) => AppStatus::Disconnected(last_id + 1, last_id),
-        };
-        *worker_tx = None;
+    pub fn submit_time(&self) -> Option<u64> {
+        self.transitions.first().map(|transition| transition.time)
+    }
+
+    // of the last run if requeued
+    pub fn start_time(&self) -> Option<u64> {
+        self.last_time(|status| status == TaskStatus::Running)
+    }
+
+    // also when canceled
+    pub fn finish_time(&self) -> Option<u64> {
+        self.last_time(|status| status == TaskStatus::Finished || status == TaskStatus::Canceled)
     }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TaskStatus {
+    Pending,
+    Running,
+    Finished,
+    Canceled,
+}
+
+// pushed to task pages, receivers filter by task id themselves
+#[derive(Debug, Clone)]
+pub enum TaskEvent {
+    Status(TaskId, TaskStatus),
+    Queue, // queue or workers changed, so position and wait time may change
+    Output(TaskId, usize, Arc<str>), // with offset in output
+}
+
+// output beyond is dropped
+pub const OUTPUT_LIMIT: usize = 10_000_000;
 
+impl<P> App<P> {
     pub async fn get_task(&self, task_id: TaskId) -> anyhow::Result<Task<P>>
     where
-        P: Clone + for<'a> Deser<'a>,
+        P: Preset,
     {
         if let Some(task) = self.data.read().await.task_table.get(&task_id) {
             return Ok(Task {
@@ -161,56 +248,145 @@ impl<P> App<P> {
                 preset: task.preset.clone(),
                 upload: Vec::new(), // any better way?
                 status: task.status,
+                retry: task.retry,
+                exit_status: task.exit_status,
+                results: task.results.clone(),
+                transitions: task.transitions.clone(),
             });
         }
-        let mut query: HashMap<String, String> = self
-            .client
-            .get_async_connection()
-            .await?
-            .hgetall(format!("task:{}", task_id))
-            .await?;
+        let mut query = self.store.get_task(task_id).await?;
         if query.is_empty() {
             return Err(anyhow!("task not found"));
         }
         Ok(Task {
             user_id: query.remove("user-id").unwrap(),
-            preset: from_str(query.get("preset").unwrap()).unwrap(),
+            preset: P::from_record(&query)?,
             upload: Vec::new(),
             status: from_str(query.get("status").unwrap()).unwrap(),
+            retry: query
+                .get("retry")
+                .map(|retry| retry.parse().unwrap())
+                .unwrap_or(0),
+            exit_status: query
+                .get("exit-status")
+                .map(|exit_status| from_str(exit_status).unwrap())
+                .unwrap_or_default(),
+            results: query
+                .get("results")
+                .map(|results| from_str(results).unwrap())
+                .unwrap_or_default(),
+            transitions: transitions_field(&query),
         })
     }
 
     pub async fn replace_upload(&self, task_id: TaskId, upload: Vec<u8>) -> anyhow::Result<()> {
         let mut data = self.data.write().await;
-        let task = data.task_table.get_mut(&task_id).unwrap();
+        // only pending or running ones are guaranteed in `task_table`
+        let task = data
+            .task_table
+            .get_mut(&task_id)
+            .ok_or(anyhow!("task is not pending"))?;
         if task.status != TaskStatus::Pending {
             return Err(anyhow!("task is not pending"));
         }
+        self.store.put_upload(task_id, &upload).await?;
         task.upload = upload;
         Ok(())
     }
 
-    pub async fn cancel_task(&self, task_id: TaskId) -> anyhow::Result<()> {
+    pub async fn cancel_task(&self, task_id: TaskId, actor: Actor) -> anyhow::Result<()> {
+        let mut status = self.status.write().await;
         let mut data = self.data.write().await;
-        let task = data.task_table.get_mut(&task_id).unwrap();
-        if task.status != TaskStatus::Pending {
+        // only pending or running ones are guaranteed in `task_table`
+        let task = data
+            .task_table
+            .get_mut(&task_id)
+            .ok_or(anyhow!("task is not pending or running"))?;
+        match task.status {
+            TaskStatus::Pending => assert!(status.queue.remove(task_id)),
+            TaskStatus::Running => {
+                let worker_id = status
+                    .workers
+                    .iter()
+                    .find(|(_, &running)| running == Some(task_id))
+                    .map(|(&worker_id, _)| worker_id)
+                    .unwrap();
+                let worker_table = self.worker_table.lock().await;
+                let worker = &worker_table[&worker_id];
+                if !worker
+                    .capabilities
+                    .iter()
+                    .any(|capability| capability == protocol::CAPABILITY_CANCEL)
+                {
+                    return Err(anyhow!("worker {} cannot cancel running task", worker.name));
+                }
+                // the worker stays busy until it finishes the canceled task
+                status.usage.settle(task_id);
+                let _ = worker.sender.send(ToWorker::Cancel { task_id });
+            }
+            _ => return Err(anyhow!("task is not pending or running")),
+        }
+        self.store.remove_upload(task_id).await?;
+        self.set_status(task_id, task, TaskStatus::Canceled, actor)
+            .await;
+        let _ = self.events.send(TaskEvent::Queue);
+        Ok(())
+    }
+
+    pub async fn move_to_front(&self, task_id: TaskId) -> anyhow::Result<()> {
+        let mut status = self.status.write().await;
+        if !status.queue.promote(task_id) {
             return Err(anyhow!("task is not pending"));
         }
-        task.status = TaskStatus::Canceled;
+        let _ = self.events.send(TaskEvent::Queue);
+        Ok(())
+    }
 
-        let _: () = self
-            .client
-            .get_async_connection()
+    pub async fn get_output(&self, task_id: TaskId) -> anyhow::Result<Vec<u8>> {
+        self.store
+            .get_output(task_id)
             .await?
-            .hset(
-                format!("task:{}", task_id),
-                "status",
-                to_string(&TaskStatus::Canceled).unwrap(),
+            .ok_or(anyhow!("no available output"))
+    }
+
+    async fn set_status(
+        &self,
+        task_id: TaskId,
+        task: &mut Task<P>,
+        status: TaskStatus,
+        actor: Actor,
+    ) {
+        task.status = status;
+        if status == TaskStatus::Finished || status == TaskStatus::Canceled {
+            // never run again, and finished tasks stay in `task_table`
+            task.upload = Vec::new();
+        }
+        task.transitions.push(Transition {
+            time: timestamp(),
+            status,
+            actor,
+        });
+        self.store
+            .set_task(
+                task_id,
+                &[
+                    ("status", to_string(&status).unwrap()),
+                    ("transitions", to_string(&task.transitions).unwrap()),
+                ],
             )
             .await
-            .unwrap();
+            .unwrap(); // internal communication must success
 
-        Ok(())
+        // no receiver is fine
+        let _ = self.events.send(TaskEvent::Status(task_id, status));
+    }
+
+    async fn worker_actor(&self, worker_id: WorkerId) -> Actor {
+        Actor::Worker(self.worker_table.lock().await[&worker_id].name.clone())
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TaskEvent> {
+        self.events.subscribe()
     }
 
     // more efficient version of `app.get_task(task_id).user_id == user_id`
@@ -226,68 +402,353 @@ impl<P> App<P> {
 }
 
 impl<P: Preset> App<P> {
+    pub async fn new(store: Arc<dyn TaskStore>, config: &Config) -> anyhow::Result<Self> {
+        let mut last_id = 0;
+        let mut user_table: HashMap<_, Vec<_>> = HashMap::new();
+        let mut task_table = HashMap::new();
+        let mut queue = TaskQueue::new();
+        let mut run_time = RunTime::default();
+        loop {
+            let mut query = store.get_task(last_id + 1).await?;
+            if query.is_empty() {
+                break;
+            }
+            last_id += 1;
+            let user_id = query.remove("user-id").unwrap();
+            user_table.entry(user_id.clone()).or_default().push(last_id);
+
+            let status: TaskStatus = from_str(query.get("status").unwrap()).unwrap();
+            if status == TaskStatus::Finished {
+                let transitions = transitions_field(&query);
+                let time = |entered| {
+                    transitions
+                        .iter()
+                        .rev()
+                        .find(|transition| transition.status == entered)
+                        .map(|transition| transition.time)
+                };
+                if let (Some(start_time), Some(finish_time)) =
+                    (time(TaskStatus::Running), time(TaskStatus::Finished))
+                {
+                    // lab description may have changed since, which should not
+                    // stop the server from starting
+                    match P::from_record(&query) {
+                        Ok(preset) => run_time
+                            .record(preset.to_string(), finish_time.saturating_sub(start_time)),
+                        Err(error) => println!(
+                            "[app] warning: skip run time of task #{}: {}",
+                            last_id, error
+                        ),
+                    }
+                }
+            }
+            if status != TaskStatus::Pending && status != TaskStatus::Running {
+                continue;
+            }
+            // running task is interrupted by restart, so run it again from start
+            let mut transitions = transitions_field(&query);
+            let new_status = match (P::from_record(&query), store.get_upload(last_id).await?) {
+                (Ok(preset), Some(upload)) => {
+                    task_table.insert(
+                        last_id,
+                        Task {
+                            user_id,
+                            preset,
+                            upload,
+                            status: TaskStatus::Pending,
+                            retry: query
+                                .get("retry")
+                                .map(|retry| retry.parse())
+                                .transpose()?
+                                .unwrap_or(0),
+                            exit_status: None,
+                            results: Vec::new(),
+                            transitions: Vec::new(), // filled below
+                        },
+                    );
+                    queue.push(last_id);
+                    TaskStatus::Pending
+                }
+                // e.g., its lab is removed from lab directory, which should not
+                // stop the server from starting either
+                (Err(error), _) => {
+                    println!("[app] warning: cancel task #{}: {}", last_id, error);
+                    store.remove_upload(last_id).await?;
+                    TaskStatus::Canceled
+                }
+                (Ok(_), None) => {
+                    println!("[app] Cancel task #{} because upload is lost", last_id);
+                    TaskStatus::Canceled
+                }
+            };
+            if new_status != status {
+                transitions.push(Transition {
+                    time: timestamp(),
+                    status: new_status,
+                    actor: Actor::System,
+                });
+                store
+                    .set_task(
+                        last_id,
+                        &[
+                            ("status", to_string(&new_status).unwrap()),
+                            ("transitions", to_string(&transitions).unwrap()),
+                        ],
+                    )
+                    .await?;
+            }
+            if let Some(task) = task_table.get_mut(&last_id) {
+                task.transitions = transitions;
+            }
+        }
+        println!(
+            "[app] Initialized with {} past tasks, {} requeued",
+            last_id,
+            queue.len()
+        );
+
+        Ok(Self {
+            status: RwLock::new(AppStatus {
+                workers: BTreeMap::new(),
+                queue,
+                policy: config.policy,
+                usage: Usage::default(),
+                run_time,
+                mode: DispatchMode::Open,
+                last_id,
+                next_worker: 0,
+            }),
+            worker_table: Mutex::new(HashMap::new()),
+            data: RwLock::new(AppData {
+                task_table,
+                user_table,
+            }),
+            store,
+            events: broadcast::channel(256).0,
+            ping_interval: Duration::from_secs(config.ping_interval),
+            grace_period: Duration::from_secs(config.grace_period),
+            retry_limit: config.retry_limit,
+            worker_secret: config.worker_secret.clone(),
+        })
+    }
+
+    // the time each worker becomes free, assuming every task takes `get_time`,
+    // counting from start for running ones
+    fn estimate_wait_time(
+        status: &AppStatus,
+        data: &AppData<P>,
+        task_id: TaskId,
+        get_time: impl Fn(TaskId) -> u64,
+    ) -> Duration
+    where
+        P: Preset,
+    {
+        let now = timestamp();
+        let mut worker_free: Vec<_> = status
+            .workers
+            .values()
+            .map(|running| {
+                running
+                    .map(|task_id| {
+                        let elapsed = data.task_table[&task_id]
+                            .start_time()
+                            .map(|start_time| now.saturating_sub(start_time))
+                            .unwrap_or(0);
+                        get_time(task_id).saturating_sub(elapsed)
+                    })
+                    .unwrap_or(0)
+            })
+            .collect();
+        if worker_free.is_empty() {
+            worker_free.push(0); // estimate as if one worker would connect
+        }
+        for pending_id in Self::plan(status, data)
+            .into_iter()
+            .take_while(|&id| id != task_id)
+        {
+            *worker_free.iter_mut().min().unwrap() += get_time(pending_id);
+        }
+        Duration::from_secs(worker_free.into_iter().min().unwrap())
+    }
+
+    pub async fn set_mode(&self, mode: DispatchMode) {
+        let mut status = self.status.write().await;
+        println!("[app] dispatch mode {}", mode);
+        status.mode = mode;
+        self.dispatch(&mut status).await;
+    }
+
+    // running tasks, then pending ones in dispatch order
+    pub async fn outstanding_tasks(&self) -> Vec<(TaskId, Task<P>)>
+    where
+        P: Preset,
+    {
+        let status = self.status.read().await;
+        let data = self.data.read().await;
+        status
+            .workers
+            .values()
+            .flatten()
+            .copied()
+            .chain(Self::plan(&status, &data))
+            .map(|task_id| {
+                let task = &data.task_table[&task_id];
+                let task = Task {
+                    upload: Vec::new(),
+                    ..task.clone()
+                };
+                (task_id, task)
+            })
+            .collect()
+    }
+
+    // maximum, as if every task runs until timeout
     pub async fn get_wait_time(&self, task_id: TaskId) -> Duration
     where
         P: Preset,
     {
-        let start_task = match *self.status.read().await {
-            AppStatus::Disconnected(pending_id, _) => pending_id,
-            AppStatus::Running(task_id, _) => task_id,
-            AppStatus::StandBy(_) => return Duration::from_secs(0),
+        let status = self.status.read().await;
+        let data = self.data.read().await;
+        Self::estimate_wait_time(&status, &data, task_id, |task_id| {
+            data.task_table[&task_id].preset.get_timeout()
+        })
+    }
+
+    // as if every task takes the average of recent runs of its preset, or its
+    // timeout if never finished before
+    pub async fn get_expected_wait_time(&self, task_id: TaskId) -> Duration
+    where
+        P: Preset,
+    {
+        let status = self.status.read().await;
+        let data = self.data.read().await;
+        Self::estimate_wait_time(&status, &data, task_id, |task_id| {
+            let preset = &data.task_table[&task_id].preset;
+            let timeout = preset.get_timeout();
+            status
+                .run_time
+                .expect(&preset.to_string())
+                .map_or(timeout, |run_time| run_time.min(timeout))
+        })
+    }
+
+    // number of pending tasks that will be dispatched before this one
+    pub async fn get_position(&self, task_id: TaskId) -> Option<usize> {
+        let status = self.status.read().await;
+        let data = self.data.read().await;
+        Self::plan(&status, &data)
+            .into_iter()
+            .position(|id| id == task_id)
+    }
+
+    // close reason if the worker does not start with a compatible hello
+    async fn handshake(&self, websocket: &mut WebSocket) -> Result<(String, Vec<String>), String> {
+        let message = loop {
+            match websocket.next().await {
+                Some(Ok(message)) if message.is_binary() => break message,
+                Some(Ok(message)) if message.is_close() => return Err(String::new()),
+                Some(Ok(_)) => continue,
+                Some(Err(error)) => return Err(error.to_string()),
+                None => return Err(String::new()),
+            }
         };
-        let wait_time = self
-            .data
-            .read()
-            .await
-            .task_table
-            .iter()
-            .filter(|(id, task)| {
-                (start_task..task_id).contains(id)
-                    && (task.status == TaskStatus::Pending || task.status == TaskStatus::Running)
-            })
-            .map(|(_, task)| task.preset.get_timeout())
-            .sum();
-        Duration::from_secs(wait_time)
+        match from_slice(message.as_bytes()) {
+            Ok(FromWorker::Hello {
+                version,
+                secret,
+                name,
+                capabilities,
+            }) => {
+                if version != protocol::VERSION {
+                    return Err(format!(
+                        "protocol version {} is not supported, expect {}",
+                        version,
+                        protocol::VERSION
+                    ));
+                }
+                if secret != self.worker_secret {
+                    return Err(String::from("wrong worker secret"));
+                }
+                Ok((name, capabilities))
+            }
+            Ok(_) => Err(String::from("expect hello as first message")),
+            Err(error) => Err(format!("invalid message: {}", error)),
+        }
     }
 
     pub async fn connect_worker(self: &Arc<Self>, mut websocket: WebSocket)
     where
         P: 'static + Send,
     {
-        let mut status = self.status.write().await;
-        let mut worker_tx = self.worker_tx.lock().await;
+        let (name, capabilities) =
+            match timeout(self.ping_interval, self.handshake(&mut websocket)).await {
+                Ok(Ok(worker)) => worker,
+                Ok(Err(reason)) => {
+                    println!("[app] reject worker: {}", reason);
+                    let _ = websocket.send(Message::close_with(1002u16, reason)).await;
+                    return;
+                }
+                Err(_) => {
+                    println!("[app] reject worker: no hello");
+                    let _ = websocket
+                        .send(Message::close_with(1002u16, "no hello"))
+                        .await;
+                    return;
+                }
+            };
 
-        if worker_tx.is_some() {
-            unimplemented!("multiple worker");
+        let worker_id = {
+            let mut status = self.status.write().await;
+            status.next_worker += 1;
+            status.next_worker - 1
+        };
+        // not holding `status`, so a slow worker stalls nobody else
+        let welcome = ToWorker::Welcome { worker_id };
+        if websocket
+            .send(Message::binary(to_vec_named(&welcome).unwrap()))
+            .await
+            .is_err()
+        {
+            return;
         }
-        let (worker_tx0, mut worker_rx) = mpsc::channel(1);
-        *worker_tx = Some(worker_tx0);
-        drop(worker_tx); // transfer to `send_task`
 
-        *status = if let AppStatus::Disconnected(pending_id, last_id) = *status {
-            if pending_id <= last_id {
-                if let Some(task_id) = self.send_task(pending_id).await {
-                    AppStatus::Running(task_id, last_id)
-                } else {
-                    AppStatus::StandBy(last_id)
-                }
-            } else {
-                AppStatus::StandBy(last_id)
-            }
-        } else {
-            unreachable!();
+        let (worker_tx, mut worker_rx) = mpsc::unbounded_channel();
+        println!(
+            "[app] Worker {} ({}) connected, capabilities: {:?}",
+            worker_id, name, capabilities
+        );
+        let worker = Worker {
+            sender: worker_tx,
+            name,
+            capabilities,
         };
+        let mut status = self.status.write().await;
+        self.worker_table.lock().await.insert(worker_id, worker);
+        status.workers.insert(worker_id, None);
+        self.dispatch(&mut status).await;
+        drop(status);
 
         let app = self.clone();
         spawn(async move {
             let mut worker_deadline = None;
+            let mut output_length = 0;
+            let mut close_reason = None;
             loop {
                 select! {
                     Some(to_worker) = worker_rx.recv() => {
                         if websocket.send(Message::binary(to_vec_named(&to_worker).unwrap())).await.is_err() {
                             break;
                         }
-                        worker_deadline = Some(Instant::now() + Duration::from_secs(to_worker.timeout + 5));
+                        match to_worker {
+                            ToWorker::Run { timeout, .. } => {
+                                worker_deadline = Some(Instant::now() + Duration::from_secs(timeout) + app.grace_period);
+                                output_length = 0;
+                            }
+                            ToWorker::Cancel { .. } => {
+                                worker_deadline = Some(Instant::now() + app.grace_period);
+                            }
+                            ToWorker::Welcome { .. } => unreachable!(),
+                        }
                     }
                     Some(Ok(message)) = websocket.next() => {
                         if message.is_close() {
@@ -299,15 +760,39 @@ impl<P: Preset> App<P> {
                             }
                             continue;
                         }
-                        let from_worker: FromWorker = from_slice(&message.into_bytes()).unwrap();
-                        app.finish_task(from_worker).await;
-
-                        worker_deadline = None;
+                        let result = match from_slice(message.as_bytes()) {
+                            Ok(FromWorker::Output { task_id, mut output }) => {
+                                if output_length >= OUTPUT_LIMIT {
+                                    continue;
+                                }
+                                let offset = output_length;
+                                if offset + output.len() >= OUTPUT_LIMIT {
+                                    let mut length = OUTPUT_LIMIT - offset;
+                                    while !output.is_char_boundary(length) {
+                                        length -= 1;
+                                    }
+                                    output.truncate(length);
+                                    output.push_str("\n*** Output truncated.\n");
+                                }
+                                output_length += output.len();
+                                app.append_output(worker_id, task_id, offset, output).await
+                            }
+                            Ok(FromWorker::Finish { task_id, exit_status }) => {
+                                worker_deadline = None;
+                                app.finish_task(worker_id, task_id, exit_status).await
+                            }
+                            Ok(FromWorker::Hello { .. }) => Err(anyhow!("unexpected hello")),
+                            Err(error) => Err(anyhow!("invalid message: {}", error)),
+                        };
+                        if let Err(error) = result {
+                            close_reason = Some(error.to_string());
+                            break;
+                        }
                     }
-                    _ = sleep(Duration::from_secs(10)) => {
+                    _ = sleep(app.ping_interval) => {
                         if let Some(worker_deadline) = worker_deadline {
                             if Instant::now() > worker_deadline {
-                                println!("[app] disconnect worker because no response");
+                                close_reason = Some(String::from("no response"));
                                 break;
                             }
                         }
@@ -318,95 +803,199 @@ impl<P: Preset> App<P> {
                     else => break,
                 }
             }
-            websocket.close().await.unwrap();
-            app.disconnect_worker().await;
+            if let Some(reason) = close_reason {
+                println!("[app] disconnect worker {}: {}", worker_id, reason);
+                let _ = websocket.send(Message::close_with(1002u16, reason)).await;
+            }
+            let _ = websocket.close().await;
+            app.disconnect_worker(worker_id).await;
         });
     }
 
-    async fn finish_task(&self, from_worker: FromWorker) {
+    async fn disconnect_worker(&self, worker_id: WorkerId) {
         let mut status = self.status.write().await;
-        let mut data = self.data.write().await;
-        if let AppStatus::Running(task_id, last_id) = *status {
-            assert_eq!(task_id, from_worker.task_id);
+        self.worker_table.lock().await.remove(&worker_id);
+        println!("[app] Worker {} disconnected", worker_id);
+        // dispatch even without a task to requeue, for one less worker for wait
+        // time, and a draining queue may have no running task left
+        let task_id = if let Some(task_id) = status.workers.remove(&worker_id).unwrap() {
+            task_id
+        } else {
+            self.dispatch(&mut status).await;
+            return;
+        };
+        status.usage.settle(task_id);
 
-            fs::write(format!("_fs/output/{}", task_id), from_worker.output)
+        let mut data = self.data.write().await;
+        let task = data.task_table.get_mut(&task_id).unwrap();
+        if task.status == TaskStatus::Canceled {
+            // canceled during running, nothing to clean up
+        } else if task.retry < self.retry_limit {
+            println!("[app] Requeue task #{}", task_id);
+            task.retry += 1;
+            self.store
+                .set_task(task_id, &[("retry", task.retry.to_string())])
                 .await
                 .unwrap();
-            println!("[app] finish write _fs/output/{}", task_id);
+            self.set_status(task_id, task, TaskStatus::Pending, Actor::System)
+                .await;
+            // ahead of others no matter how policy orders them
+            status.queue.push(task_id);
+            status.queue.promote(task_id);
+        } else {
+            self.set_status(task_id, task, TaskStatus::Canceled, Actor::System)
+                .await;
+            self.store.remove_upload(task_id).await.unwrap();
+        }
+        drop(data); // transfer to `dispatch`
+        self.dispatch(&mut status).await;
+    }
 
-            data.task_table.get_mut(&task_id).unwrap().status = TaskStatus::Finished;
-            drop(data); // transfer to `send_task`
+    async fn append_output(
+        &self,
+        worker_id: WorkerId,
+        task_id: TaskId,
+        offset: usize,
+        output: String,
+    ) -> anyhow::Result<()> {
+        let status = self.status.read().await;
+        if status.workers[&worker_id] != Some(task_id) {
+            return Err(anyhow!("output of task #{} not running", task_id));
+        }
+        self.store
+            .append_output(task_id, output.as_bytes())
+            .await
+            .unwrap();
+        let _ = self
+            .events
+            .send(TaskEvent::Output(task_id, offset, output.into()));
+        Ok(())
+    }
 
-            let _: () = self
-                .client
-                .get_async_connection()
-                .await
-                .unwrap()
-                .hset(
-                    format!("task:{}", task_id),
-                    "status",
-                    to_string(&TaskStatus::Finished).unwrap(),
+    async fn finish_task(
+        &self,
+        worker_id: WorkerId,
+        task_id: TaskId,
+        exit_status: Option<i32>,
+    ) -> anyhow::Result<()> {
+        let mut status = self.status.write().await;
+        if status.workers[&worker_id] != Some(task_id) {
+            return Err(anyhow!("finish of task #{} not running", task_id));
+        }
+        let mut data = self.data.write().await;
+        println!(
+            "[app] finish task #{} with exit status {:?}",
+            task_id, exit_status
+        );
+
+        let actor = self.worker_actor(worker_id).await;
+        let task = data.task_table.get_mut(&task_id).unwrap();
+        // canceled during running, already cleaned up by `cancel_task`
+        if task.status != TaskStatus::Canceled {
+            task.exit_status = exit_status;
+            let output = self.store.get_output(task_id).await.unwrap();
+            task.results = report::parse(&String::from_utf8_lossy(&output.unwrap_or_default()));
+            self.store.remove_upload(task_id).await.unwrap();
+            self.store
+                .set_task(
+                    task_id,
+                    &[
+                        ("exit-status", to_string(&exit_status).unwrap()),
+                        ("results", to_string(&task.results).unwrap()),
+                    ],
                 )
                 .await
                 .unwrap();
+            self.set_status(task_id, task, TaskStatus::Finished, actor)
+                .await;
+            if let (Some(start_time), Some(finish_time)) = (task.start_time(), task.finish_time()) {
+                let preset = task.preset.to_string();
+                status
+                    .run_time
+                    .record(preset, finish_time.saturating_sub(start_time));
+            }
+            status.usage.settle(task_id);
+        }
+        drop(data); // transfer to `send_task`
 
-            let pending_id = task_id + 1;
-            *status = if pending_id > last_id {
-                AppStatus::StandBy(last_id)
-            } else if let Some(task_id) = self.send_task(pending_id).await {
-                AppStatus::Running(task_id, last_id)
+        status.workers.insert(worker_id, None);
+        self.dispatch(&mut status).await;
+        Ok(())
+    }
+
+    fn plan(status: &AppStatus, data: &AppData<P>) -> Vec<TaskId> {
+        status.queue.plan(status.policy, &status.usage, |task_id| {
+            let task = &data.task_table[&task_id];
+            (&task.user_id, task.preset.get_timeout())
+        })
+    }
+
+    // send pending tasks to idle workers until either one runs out
+    async fn dispatch(&self, status: &mut AppStatus) {
+        if status.mode == DispatchMode::Draining && status.workers.values().all(Option::is_none) {
+            println!("[app] drained, dispatch mode {}", DispatchMode::Paused);
+            status.mode = DispatchMode::Paused;
+        }
+        if status.mode == DispatchMode::Paused || status.mode == DispatchMode::Draining {
+            let _ = self.events.send(TaskEvent::Queue);
+            return;
+        }
+        let idle_list: Vec<_> = status
+            .workers
+            .iter()
+            .filter(|(_, running)| running.is_none())
+            .map(|(&worker_id, _)| worker_id)
+            .collect();
+        for worker_id in idle_list {
+            let data = self.data.read().await;
+            let task_id = if let Some(&task_id) = Self::plan(status, &data).first() {
+                task_id
             } else {
-                AppStatus::StandBy(last_id)
+                break;
             };
-        } else {
-            unreachable!();
+            let task = &data.task_table[&task_id];
+            status
+                .usage
+                .charge(task_id, &task.user_id, task.preset.get_timeout());
+            drop(data); // transfer to `send_task`
+
+            status.queue.remove(task_id);
+            self.send_task(worker_id, task_id).await;
+            status.workers.insert(worker_id, Some(task_id));
         }
+        let _ = self.events.send(TaskEvent::Queue);
     }
 
-    async fn send_task(&self, mut pending_id: TaskId) -> Option<TaskId> {
+    async fn send_task(&self, worker_id: WorkerId, task_id: TaskId) {
         let mut data = self.data.write().await;
-        let task_table = &mut data.task_table;
-        let worker_tx = self.worker_tx.lock().await;
-
-        let to_worker = loop {
-            if let Some(task) = task_table.get_mut(&pending_id) {
-                match task.status {
-                    TaskStatus::Pending => {
-                        break ToWorker {
-                            task_id: pending_id,
-                            command: task.preset.get_command(),
-                            upload: take(&mut task.upload),
-                            timeout: task.preset.get_timeout(),
-                        }
-                    }
-                    TaskStatus::Canceled => pending_id += 1,
-                    _ => unreachable!(),
-                }
-            } else {
-                return None;
-            }
+        let task = data.task_table.get_mut(&task_id).unwrap();
+        assert_eq!(task.status, TaskStatus::Pending);
+        let to_worker = ToWorker::Run {
+            task_id,
+            command: task.preset.get_command(),
+            upload: task.upload.clone(), // kept for requeue until `set_status` clears it
+            timeout: task.preset.get_timeout(),
         };
-        task_table.get_mut(&pending_id).unwrap().status = TaskStatus::Running;
 
-        let _: () = self
-            .client
-            .get_async_connection()
-            .await
-            .unwrap()
-            .hset(
-                format!("task:{}", pending_id),
-                "status",
-                to_string(&TaskStatus::Running).unwrap(),
-            )
-            .await
-            .unwrap(); // internal communication must success
+        // output of an interrupted run is discarded as well
+        self.store.put_output(task_id, &[]).await.unwrap();
+        let actor = self.worker_actor(worker_id).await;
+        self.set_status(task_id, task, TaskStatus::Running, actor)
+            .await;
 
-        worker_tx.as_ref().unwrap().send(to_worker).await.unwrap();
-        Some(pending_id)
+        // if the worker is gone already, `disconnect_worker` will clean up the task
+        let _ = self.worker_table.lock().await[&worker_id]
+            .sender
+            .send(to_worker);
     }
 
-    pub async fn push_task(&self, task: Task<P>) -> anyhow::Result<TaskId> {
+    pub async fn push_task(&self, mut task: Task<P>) -> anyhow::Result<TaskId> {
         let mut status = self.status.write().await;
+        if status.mode == DispatchMode::Closed {
+            return Err(anyhow!(
+                "submission is closed for maintenance, please try again later"
+            ));
+        }
         let data = self.data.read().await;
 
         let user_last = data
@@ -415,43 +1004,29 @@ impl<P: Preset> App<P> {
             .and_then(|task_list| task_list.last())
             .and_then(|last_task| {
                 // assert anything not present in `task_table` is unrelated
-                let status = data.task_table.get(&last_task)?.status;
+                let status = data.task_table.get(last_task)?.status;
                 if status == TaskStatus::Pending || status == TaskStatus::Running {
                     Some(last_task)
                 } else {
                     None
                 }
             })
-            .cloned()
-            .unwrap_or(0);
+            .cloned();
         drop(data); // transfer lock to `register_task`
 
-        let task_id = match *status {
-            AppStatus::Disconnected(pending_id, last_id) => {
-                if user_last >= pending_id {
-                    return Err(anyhow!("already pending for #{}", user_last));
-                }
-                last_id
-            }
-            AppStatus::Running(task_id, last_id) => {
-                if user_last >= task_id {
-                    return Err(anyhow!("already pending/running for #{}", user_last));
-                }
-                last_id
-            }
-            AppStatus::StandBy(last_id) => last_id,
-        } + 1;
+        if let Some(user_last) = user_last {
+            return Err(anyhow!("already pending/running for #{}", user_last));
+        }
+        let task_id = status.last_id + 1;
+        task.transitions = vec![Transition {
+            time: timestamp(),
+            status: TaskStatus::Pending,
+            actor: Actor::User(task.user_id.clone()),
+        }];
         self.register_task(task_id, task).await;
-
-        *status = match *status {
-            AppStatus::Disconnected(id, _) => AppStatus::Disconnected(id, task_id),
-            AppStatus::Running(id, _) => AppStatus::Running(id, task_id),
-            AppStatus::StandBy(_) => {
-                let id = self.send_task(task_id).await;
-                assert_eq!(id, Some(task_id));
-                AppStatus::Running(task_id, task_id)
-            }
-        };
+        status.last_id = task_id;
+        status.queue.push(task_id);
+        self.dispatch(&mut status).await;
         Ok(task_id)
     }
 
@@ -467,20 +1042,667 @@ impl<P: Preset> App<P> {
             .or_default()
             .push(task_id);
 
-        let _: () = self
-            .client
-            .get_async_connection()
-            .await
-            .unwrap()
-            .hset_multiple(
-                format!("task:{}", task_id),
+        // write upload before the task is visible in store, so a restarted
+        // server never sees a pending task without upload
+        self.store.put_upload(task_id, &task.upload).await.unwrap();
+        self.store
+            .set_task(
+                task_id,
                 &[
-                    ("user-id", &task.user_id),
-                    ("preset", &to_string(&task.preset).unwrap()),
-                    ("status", &to_string(&task.status).unwrap()),
-                ],
+                    ("user-id", task.user_id.clone()),
+                    ("status", to_string(&task.status).unwrap()),
+                    ("transitions", to_string(&task.transitions).unwrap()),
+                ]
+                .into_iter()
+                .chain(task.preset.to_record())
+                .collect::<Vec<_>>(),
             )
             .await
             .unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lab;
+    use crate::presets::data;
+    use crate::store::MemoryStore;
+    use std::path::Path;
+    use warp::test::WsClient;
+    use warp::Filter;
+
+    type TestApp = App<lab::Preset>;
+
+    const SECRET: &str = "secret";
+
+    fn new_config() -> Config {
+        Config {
+            worker_secret: String::from(SECRET),
+            ..Config::default()
+        }
+    }
+
+    async fn new_app(store: Arc<dyn TaskStore>) -> Arc<TestApp> {
+        new_app_with(store, new_config()).await
+    }
+
+    async fn new_app_with(store: Arc<dyn TaskStore>, config: Config) -> Arc<TestApp> {
+        data::load(Path::new("labs")).unwrap();
+        Arc::new(App::new(store, &config).await.unwrap())
+    }
+
+    // lab 4 part 1 test `test`, with user id as upload
+    fn new_task(user_id: &str, test: u32) -> Task<lab::Preset> {
+        let form: HashMap<_, _> = [
+            (":lab", String::from("lab4")),
+            (":test", format!("[1,{}]", test)),
+            (":log_level", String::from("disable")),
+            (":check", String::from("no")),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+        Task {
+            user_id: user_id.to_string(),
+            preset: form.try_into().unwrap(),
+            upload: user_id.as_bytes().to_vec(),
+            status: TaskStatus::Pending,
+            retry: 0,
+            exit_status: None,
+            results: Vec::new(),
+            transitions: Vec::new(),
+        }
+    }
+
+    // before hello
+    async fn open(app: &Arc<TestApp>) -> WsClient {
+        let app = app.clone();
+        let route = warp::ws().map(move |ws: warp::ws::Ws| {
+            let app = app.clone();
+            ws.on_upgrade(move |websocket| async move { app.connect_worker(websocket).await })
+        });
+        warp::test::ws().handshake(route).await.unwrap()
+    }
+
+    fn hello(version: u32, secret: &str) -> FromWorker {
+        FromWorker::Hello {
+            version,
+            secret: secret.to_string(),
+            name: String::from("test"),
+            capabilities: vec![String::from(protocol::CAPABILITY_CANCEL)],
+        }
+    }
+
+    async fn connect(app: &Arc<TestApp>, secret: &str) -> WsClient {
+        let mut worker = open(app).await;
+        send(&mut worker, &hello(protocol::VERSION, secret)).await;
+        worker
+    }
+
+    async fn send(worker: &mut WsClient, from_worker: &FromWorker) {
+        worker
+            .send(Message::binary(to_vec_named(from_worker).unwrap()))
+            .await;
+    }
+
+    async fn recv(worker: &mut WsClient) -> ToWorker {
+        loop {
+            let message = timeout(Duration::from_secs(5), worker.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            if message.is_binary() {
+                return from_slice(message.as_bytes()).unwrap();
+            }
+        }
+    }
+
+    async fn recv_run(worker: &mut WsClient) -> (TaskId, Vec<u8>) {
+        match recv(worker).await {
+            ToWorker::Run {
+                task_id, upload, ..
+            } => (task_id, upload),
+            to_worker => panic!("expect run, received {:?}", to_worker),
+        }
+    }
+
+    // worker messages are handled in background
+    async fn wait_status(app: &TestApp, task_id: TaskId, status: TaskStatus) {
+        timeout(Duration::from_secs(5), async {
+            while app.get_task(task_id).await.unwrap().status != status {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    fn last_transition(task: &Task<lab::Preset>) -> (TaskStatus, Actor) {
+        let transition = task.transitions.last().unwrap();
+        (transition.status, transition.actor.clone())
+    }
+
+    #[tokio::test]
+    async fn write_through_store() {
+        let store = Arc::new(MemoryStore::default());
+        let app = new_app(store.clone()).await;
+        let task_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        let record = store.get_task(task_id).await.unwrap();
+        assert_eq!(record["user-id"], "alice");
+        assert_eq!(record["lab"], "lab4");
+        assert_eq!(
+            from_str::<TaskStatus>(&record["status"]).unwrap(),
+            TaskStatus::Pending
+        );
+        assert_eq!(
+            store.get_upload(task_id).await.unwrap(),
+            Some(b"alice".to_vec())
+        );
+
+        let alice = Actor::User(String::from("alice"));
+        app.cancel_task(task_id, alice.clone()).await.unwrap();
+        let record = store.get_task(task_id).await.unwrap();
+        assert_eq!(
+            from_str::<TaskStatus>(&record["status"]).unwrap(),
+            TaskStatus::Canceled
+        );
+        assert_eq!(store.get_upload(task_id).await.unwrap(), None);
+
+        // finished tasks are read back from store by another app on it
+        let app = new_app(store.clone()).await;
+        let task = app.get_task(task_id).await.unwrap();
+        assert_eq!(task.user_id, "alice");
+        assert_eq!(task.preset, new_task("alice", 1).preset);
+        assert_eq!(last_transition(&task), (TaskStatus::Canceled, alice));
+        assert!(app.get_task(task_id + 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn push_and_dispatch() {
+        let store = Arc::new(MemoryStore::default());
+        let app = new_app(store.clone()).await;
+        let task_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        assert_eq!(app.get_position(task_id).await, Some(0));
+        // one outstanding task per user
+        assert!(app.push_task(new_task("alice", 2)).await.is_err());
+
+        let mut worker = connect(&app, SECRET).await;
+        assert!(matches!(recv(&mut worker).await, ToWorker::Welcome { .. }));
+        assert_eq!(recv_run(&mut worker).await, (task_id, b"alice".to_vec()));
+        wait_status(&app, task_id, TaskStatus::Running).await;
+        assert_eq!(app.get_position(task_id).await, None);
+
+        let output = "TEST 1.1: Commands return OK (5pts)\n\n...PASS (0.1s)\n";
+        let output_message = FromWorker::Output {
+            task_id,
+            output: output.to_string(),
+        };
+        send(&mut worker, &output_message).await;
+        let finish = FromWorker::Finish {
+            task_id,
+            exit_status: Some(0),
+        };
+        send(&mut worker, &finish).await;
+        wait_status(&app, task_id, TaskStatus::Finished).await;
+
+        let task = app.get_task(task_id).await.unwrap();
+        assert_eq!(task.exit_status, Some(0));
+        assert_eq!(report::score(&task.results), (5, 5));
+        assert_eq!(
+            last_transition(&task),
+            (TaskStatus::Finished, Actor::Worker(String::from("test")))
+        );
+        assert_eq!(app.get_output(task_id).await.unwrap(), output.as_bytes());
+        assert_eq!(store.get_upload(task_id).await.unwrap(), None);
+
+        let next_id = app.push_task(new_task("alice", 2)).await.unwrap();
+        assert_eq!(recv_run(&mut worker).await.0, next_id);
+    }
+
+    #[tokio::test]
+    async fn concurrent_workers() {
+        let app = new_app(Arc::new(MemoryStore::default())).await;
+        let mut worker1 = connect(&app, SECRET).await;
+        recv(&mut worker1).await;
+        let mut worker2 = connect(&app, SECRET).await;
+        recv(&mut worker2).await;
+        let alice_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        let bob_id = app.push_task(new_task("bob", 1)).await.unwrap();
+        let carol_id = app.push_task(new_task("carol", 1)).await.unwrap();
+        // whichever worker gets the first task, the other one gets the second
+        let (alice_worker, bob_worker) = timeout(Duration::from_secs(5), async {
+            loop {
+                let workers = app.status.read().await.workers.clone();
+                if workers.values().flatten().count() == 2 {
+                    break workers;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .map(|workers| {
+            let worker_of = |task_id| {
+                *workers
+                    .iter()
+                    .find(|(_, &running)| running == Some(task_id))
+                    .unwrap()
+                    .0
+            };
+            (worker_of(alice_id), worker_of(bob_id))
+        })
+        .unwrap();
+        assert_ne!(alice_worker, bob_worker);
+        assert_eq!(app.get_position(carol_id).await, Some(0));
+
+        let (mut alice_ws, mut bob_ws) = if alice_worker < bob_worker {
+            (worker1, worker2)
+        } else {
+            (worker2, worker1)
+        };
+        assert_eq!(recv_run(&mut alice_ws).await, (alice_id, b"alice".to_vec()));
+        assert_eq!(recv_run(&mut bob_ws).await, (bob_id, b"bob".to_vec()));
+
+        // the worker finishing first takes the next task
+        let finish = FromWorker::Finish {
+            task_id: bob_id,
+            exit_status: Some(0),
+        };
+        send(&mut bob_ws, &finish).await;
+        assert_eq!(recv_run(&mut bob_ws).await.0, carol_id);
+        wait_status(&app, bob_id, TaskStatus::Finished).await;
+        assert_eq!(
+            app.get_task(alice_id).await.unwrap().status,
+            TaskStatus::Running
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_wrong_secret() {
+        let app = new_app(Arc::new(MemoryStore::default())).await;
+        let task_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        let mut worker = connect(&app, "wrong").await;
+        worker.recv_closed().await.unwrap();
+        assert!(app.status.read().await.workers.is_empty());
+        assert_eq!(app.get_position(task_id).await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn reject_bad_hello() {
+        let app = new_app(Arc::new(MemoryStore::default())).await;
+        let task_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        let mut worker = open(&app).await;
+        send(&mut worker, &hello(protocol::VERSION + 1, SECRET)).await;
+        worker.recv_closed().await.unwrap();
+        let mut worker = open(&app).await;
+        let finish = FromWorker::Finish {
+            task_id,
+            exit_status: Some(0),
+        };
+        send(&mut worker, &finish).await;
+        worker.recv_closed().await.unwrap();
+        assert!(app.status.read().await.workers.is_empty());
+        assert_eq!(app.get_position(task_id).await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn record_transitions() {
+        let store = Arc::new(MemoryStore::default());
+        let app = new_app(store.clone()).await;
+        let before = timestamp();
+        let task_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        recv_run(&mut worker).await;
+        worker.send(Message::close()).await;
+        wait_status(&app, task_id, TaskStatus::Pending).await;
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        recv_run(&mut worker).await;
+        let finish = FromWorker::Finish {
+            task_id,
+            exit_status: Some(0),
+        };
+        send(&mut worker, &finish).await;
+        wait_status(&app, task_id, TaskStatus::Finished).await;
+
+        let task = app.get_task(task_id).await.unwrap();
+        let description: Vec<_> = task.transitions.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            description,
+            [
+                "submitted by user alice",
+                "started on worker test",
+                "requeued by system",
+                "started on worker test",
+                "finished on worker test",
+            ]
+        );
+        let time_list: Vec<_> = task.transitions.iter().map(|t| t.time).collect();
+        assert!(time_list.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(before <= time_list[0] && time_list[4] <= timestamp());
+        assert_eq!(task.submit_time(), Some(time_list[0]));
+        assert_eq!(task.start_time(), Some(time_list[3]));
+        assert_eq!(task.finish_time(), Some(time_list[4]));
+        // kept in store for finished tasks
+        let record = store.get_task(task_id).await.unwrap();
+        assert_eq!(transitions_field(&record), task.transitions);
+    }
+
+    #[tokio::test]
+    async fn cancel() {
+        let store = Arc::new(MemoryStore::default());
+        let app = new_app(store.clone()).await;
+        let alice_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        let bob_id = app.push_task(new_task("bob", 1)).await.unwrap();
+
+        let alice = Actor::User(String::from("alice"));
+        app.cancel_task(alice_id, alice.clone()).await.unwrap();
+        let task = app.get_task(alice_id).await.unwrap();
+        assert_eq!(
+            last_transition(&task),
+            (TaskStatus::Canceled, alice.clone())
+        );
+        assert_eq!(store.get_upload(alice_id).await.unwrap(), None);
+        assert_eq!(app.get_position(bob_id).await, Some(0));
+        assert!(app.cancel_task(alice_id, alice).await.is_err());
+
+        // a running task is killed by worker, which still reports finish
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        assert_eq!(recv_run(&mut worker).await.0, bob_id);
+        let admin = Actor::Admin(String::from("staff"));
+        app.cancel_task(bob_id, admin.clone()).await.unwrap();
+        assert!(matches!(
+            recv(&mut worker).await,
+            ToWorker::Cancel { task_id } if task_id == bob_id
+        ));
+        let finish = FromWorker::Finish {
+            task_id: bob_id,
+            exit_status: None,
+        };
+        send(&mut worker, &finish).await;
+
+        let carol_id = app.push_task(new_task("carol", 1)).await.unwrap();
+        assert_eq!(recv_run(&mut worker).await.0, carol_id);
+        let task = app.get_task(bob_id).await.unwrap();
+        assert_eq!(last_transition(&task), (TaskStatus::Canceled, admin));
+        assert_eq!(task.exit_status, None);
+    }
+
+    #[tokio::test]
+    async fn cancel_then_finish() {
+        let app = new_app(Arc::new(MemoryStore::default())).await;
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        let alice_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        assert_eq!(recv_run(&mut worker).await.0, alice_id);
+        let bob_id = app.push_task(new_task("bob", 1)).await.unwrap();
+
+        // cancel is sent to the worker, which is handling its own finish, and
+        // then the next task is sent to it before it receives the cancel
+        let status = app.status.write().await;
+        let cancel_app = app.clone();
+        let cancel = spawn(async move {
+            let alice = Actor::User(String::from("alice"));
+            cancel_app.cancel_task(alice_id, alice).await
+        });
+        sleep(Duration::from_millis(100)).await;
+        let finish = FromWorker::Finish {
+            task_id: alice_id,
+            exit_status: None,
+        };
+        send(&mut worker, &finish).await;
+        sleep(Duration::from_millis(100)).await;
+        drop(status);
+
+        timeout(Duration::from_secs(5), cancel)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            recv(&mut worker).await,
+            ToWorker::Cancel { task_id } if task_id == alice_id
+        ));
+        assert_eq!(recv_run(&mut worker).await.0, bob_id);
+        wait_status(&app, bob_id, TaskStatus::Running).await;
+        assert_eq!(
+            app.get_task(alice_id).await.unwrap().status,
+            TaskStatus::Canceled
+        );
+    }
+
+    #[tokio::test]
+    async fn requeue_on_disconnect() {
+        let store = Arc::new(MemoryStore::default());
+        let app = new_app(store.clone()).await;
+        let task_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        let retry_limit = Config::default().retry_limit;
+        for retry in 1..=retry_limit {
+            let mut worker = connect(&app, SECRET).await;
+            recv(&mut worker).await;
+            assert_eq!(recv_run(&mut worker).await, (task_id, b"alice".to_vec()));
+            worker.send(Message::close()).await;
+            wait_status(&app, task_id, TaskStatus::Pending).await;
+            let task = app.get_task(task_id).await.unwrap();
+            assert_eq!(task.retry, retry);
+            assert_eq!(last_transition(&task), (TaskStatus::Pending, Actor::System));
+            assert_eq!(app.get_position(task_id).await, Some(0));
+        }
+
+        // give up after too many retries
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        recv_run(&mut worker).await;
+        worker.send(Message::close()).await;
+        wait_status(&app, task_id, TaskStatus::Canceled).await;
+        assert_eq!(store.get_upload(task_id).await.unwrap(), None);
+        assert_eq!(app.get_position(task_id).await, None);
+    }
+
+    #[tokio::test]
+    async fn requeue_ahead_of_fair_share() {
+        let config = Config {
+            policy: Policy::FairShare,
+            ..new_config()
+        };
+        let app = new_app_with(Arc::new(MemoryStore::default()), config).await;
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        let alice_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        assert_eq!(recv_run(&mut worker).await.0, alice_id);
+        let bob_id = app.push_task(new_task("bob", 1)).await.unwrap();
+        // alice has used more worker time than bob, who would go first if
+        // both were queued normally
+        app.status.write().await.usage.charge(0, "alice", 3600);
+
+        worker.send(Message::close()).await;
+        wait_status(&app, alice_id, TaskStatus::Pending).await;
+        assert_eq!(app.get_position(alice_id).await, Some(0));
+        assert_eq!(app.get_position(bob_id).await, Some(1));
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        assert_eq!(recv_run(&mut worker).await.0, alice_id);
+        assert_eq!(app.get_position(bob_id).await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn restart_recovery() {
+        let store = Arc::new(MemoryStore::default());
+        let app = new_app(store.clone()).await;
+        let running_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        let pending_id = app.push_task(new_task("bob", 1)).await.unwrap();
+        let lost_id = app.push_task(new_task("carol", 1)).await.unwrap();
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        assert_eq!(recv_run(&mut worker).await.0, running_id);
+        wait_status(&app, running_id, TaskStatus::Running).await;
+        store.remove_upload(lost_id).await.unwrap();
+
+        // restarted on the same store
+        let app = new_app(store.clone()).await;
+        let task = app.get_task(running_id).await.unwrap();
+        assert_eq!(last_transition(&task), (TaskStatus::Pending, Actor::System));
+        assert_eq!(app.get_position(running_id).await, Some(0));
+        assert_eq!(app.get_position(pending_id).await, Some(1));
+        let task = app.get_task(lost_id).await.unwrap();
+        assert_eq!(
+            last_transition(&task),
+            (TaskStatus::Canceled, Actor::System)
+        );
+        assert_eq!(app.get_position(lost_id).await, None);
+
+        let task_id = app.push_task(new_task("dave", 1)).await.unwrap();
+        assert_eq!(task_id, lost_id + 1);
+        assert!(app.allow_access("alice", running_id).await);
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        assert_eq!(recv_run(&mut worker).await, (running_id, b"alice".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn restart_with_unknown_preset() {
+        let store = Arc::new(MemoryStore::default());
+        let app = new_app(store.clone()).await;
+        let removed_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        let pending_id = app.push_task(new_task("bob", 1)).await.unwrap();
+        store
+            .set_task(removed_id, &[("lab", String::from("removed"))])
+            .await
+            .unwrap();
+
+        let app = new_app(store.clone()).await;
+        let record = store.get_task(removed_id).await.unwrap();
+        assert_eq!(
+            from_str::<TaskStatus>(&record["status"]).unwrap(),
+            TaskStatus::Canceled
+        );
+        assert_eq!(store.get_upload(removed_id).await.unwrap(), None);
+        assert!(app.get_task(removed_id).await.is_err());
+        assert_eq!(app.get_position(pending_id).await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn expected_wait_time() {
+        let app = new_app(Arc::new(MemoryStore::default())).await;
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        let alice_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        assert_eq!(recv_run(&mut worker).await.0, alice_id);
+        let bob_id = app.push_task(new_task("bob", 1)).await.unwrap();
+        let carol_id = app.push_task(new_task("carol", 1)).await.unwrap();
+        let task = new_task("alice", 1);
+        let timeout = task.preset.get_timeout();
+        assert_eq!(timeout, 10);
+        let run_time = 8;
+        app.status
+            .write()
+            .await
+            .run_time
+            .record(task.preset.to_string(), run_time);
+        // alice's task has been running for 5 seconds
+        app.data
+            .write()
+            .await
+            .task_table
+            .get_mut(&alice_id)
+            .unwrap()
+            .transitions
+            .last_mut()
+            .unwrap()
+            .time -= 5;
+
+        // a second may pass since
+        let bob_wait = app.get_expected_wait_time(bob_id).await.as_secs();
+        assert!((run_time - 6..=run_time - 5).contains(&bob_wait));
+        let carol_wait = app.get_expected_wait_time(carol_id).await.as_secs();
+        assert!((bob_wait - 1..=bob_wait).contains(&(carol_wait - run_time)));
+        let bob_wait = app.get_wait_time(bob_id).await.as_secs();
+        assert!((timeout - 6..=timeout - 5).contains(&bob_wait));
+        let carol_wait = app.get_wait_time(carol_id).await.as_secs();
+        assert!((bob_wait - 1..=bob_wait).contains(&(carol_wait - timeout)));
+    }
+
+    async fn wait_mode(app: &TestApp, mode: DispatchMode) {
+        timeout(Duration::from_secs(5), async {
+            while app.status.read().await.mode != mode {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn paused() {
+        let app = new_app(Arc::new(MemoryStore::default())).await;
+        app.set_mode(DispatchMode::Paused).await;
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        let task_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(app.get_position(task_id).await, Some(0));
+        assert_eq!(
+            app.status.read().await.workers.values().flatten().count(),
+            0
+        );
+
+        app.set_mode(DispatchMode::Open).await;
+        assert_eq!(recv_run(&mut worker).await.0, task_id);
+    }
+
+    #[tokio::test]
+    async fn draining() {
+        let app = new_app(Arc::new(MemoryStore::default())).await;
+        let mut worker1 = connect(&app, SECRET).await;
+        recv(&mut worker1).await;
+        let alice_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        assert_eq!(recv_run(&mut worker1).await.0, alice_id);
+        let mut worker2 = connect(&app, SECRET).await;
+        recv(&mut worker2).await;
+        let bob_id = app.push_task(new_task("bob", 1)).await.unwrap();
+        assert_eq!(recv_run(&mut worker2).await.0, bob_id);
+
+        app.set_mode(DispatchMode::Draining).await;
+        let carol_id = app.push_task(new_task("carol", 1)).await.unwrap();
+        let finish = FromWorker::Finish {
+            task_id: alice_id,
+            exit_status: Some(0),
+        };
+        send(&mut worker1, &finish).await;
+        wait_status(&app, alice_id, TaskStatus::Finished).await;
+        assert_eq!(app.status.read().await.mode, DispatchMode::Draining);
+        assert_eq!(app.get_position(carol_id).await, Some(0));
+
+        // the last running task is canceled, and its worker is gone before
+        // reporting finish
+        let admin = Actor::Admin(String::from("staff"));
+        app.cancel_task(bob_id, admin).await.unwrap();
+        assert!(matches!(recv(&mut worker2).await, ToWorker::Cancel { .. }));
+        assert_eq!(app.status.read().await.mode, DispatchMode::Draining);
+        worker2.send(Message::close()).await;
+        wait_mode(&app, DispatchMode::Paused).await;
+        assert_eq!(app.get_position(carol_id).await, Some(0));
+
+        // nothing running at all
+        app.set_mode(DispatchMode::Draining).await;
+        assert_eq!(app.status.read().await.mode, DispatchMode::Paused);
+        app.set_mode(DispatchMode::Open).await;
+        assert_eq!(recv_run(&mut worker1).await.0, carol_id);
+    }
+
+    #[tokio::test]
+    async fn closed() {
+        let app = new_app(Arc::new(MemoryStore::default())).await;
+        let alice_id = app.push_task(new_task("alice", 1)).await.unwrap();
+        app.set_mode(DispatchMode::Closed).await;
+        assert!(app.push_task(new_task("bob", 1)).await.is_err());
+        // queued ones are still dispatched
+        let mut worker = connect(&app, SECRET).await;
+        recv(&mut worker).await;
+        assert_eq!(recv_run(&mut worker).await.0, alice_id);
+        app.set_mode(DispatchMode::Open).await;
+        app.push_task(new_task("bob", 1)).await.unwrap();
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::{anyhow, bail, Context};
+use clap::{Parser, Subcommand};
+use cs5223fet::api::{Error, Submitted, TaskInfo};
+use cs5223fet::app::{TaskId, TaskStatus};
+use cs5223fet::report::{self, TestResult};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser)]
+#[clap(about = "Submit to and watch tasks on CS5223 test runner")]
+struct Cli {
+    #[clap(long, env = "CS5223FET_URL", default_value = "http://localhost:8080")]
+    url: String,
+    /// API token created on the settings page
+    #[clap(long, env = "CS5223FET_TOKEN", hide_env_values = true)]
+    token: String,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Pack a directory, submit it and wait for output
+    Submit {
+        #[clap(long, default_value = "lab4")]
+        lab: String,
+        #[clap(long)]
+        part: u32,
+        /// Test number, 0 for the suite of the part, only for a part that has
+        /// one, e.g. part 0 and 4 of lab4
+        #[clap(long, default_value_t = 0)]
+        test: u32,
+        #[clap(long, default_value = "disable")]
+        log_level: String,
+        #[clap(long)]
+        check: bool,
+        #[clap(long, default_value = ".")]
+        dir: PathBuf,
+        /// Return after submitting instead of waiting for output
+        #[clap(long)]
+        no_wait: bool,
+        /// Save output to file instead of printing it
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// List own tasks
+    List,
+    /// Show status of a task
+    Status { id: TaskId },
+    /// Wait for a task to finish and print its output
+    Wait {
+        id: TaskId,
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// Replace upload of a pending task
+    Replace {
+        id: TaskId,
+        #[clap(long, default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Cancel a pending or running task
+    Cancel { id: TaskId },
+}
+
+// files to leave out of submission, `._*` are removed on worker anyway
+fn is_skipped(name: &str) -> bool {
+    name.starts_with("._") || name == ".git"
+}
+
+fn append_dir(
+    builder: &mut tar::Builder<impl Write>,
+    dir: &Path,
+    prefix: &Path,
+) -> anyhow::Result<()> {
+    let mut entry_list = fs::read_dir(dir)
+        .with_context(|| format!("cannot read {}", dir.display()))?
+        .collect::<Result<Vec<_>, _>>()?;
+    entry_list.sort_by_key(|entry| entry.file_name());
+    for entry in entry_list {
+        let name = entry.file_name();
+        if is_skipped(&name.to_string_lossy()) {
+            continue;
+        }
+        let (path, name) = (entry.path(), prefix.join(name));
+        if entry.file_type()?.is_dir() {
+            builder.append_dir(&name, &path)?;
+            append_dir(builder, &path, &name)?;
+        } else {
+            builder.append_path_with_name(&path, &name)?;
+        }
+    }
+    Ok(())
+}
+
+// the submit.tar.gz that is uploaded through web page
+fn pack(dir: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    append_dir(&mut builder, dir, Path::new(""))?;
+    Ok(builder.into_inner()?.finish()?)
+}
+
+struct Runner {
+    client: Client,
+    url: String,
+    token: String,
+}
+
+impl Runner {
+    fn request(
+        &self,
+        builder: impl FnOnce(&Client, String) -> RequestBuilder,
+        path: &str,
+    ) -> RequestBuilder {
+        builder(&self.client, format!("{}/api/v1/{}", self.url, path)).bearer_auth(&self.token)
+    }
+
+    async fn check(resp: Response) -> anyhow::Result<Response> {
+        if resp.status().is_success() {
+            return Ok(resp);
+        }
+        let status = resp.status();
+        match resp.json::<Error>().await {
+            Ok(Error { error }) => Err(anyhow!("{}", error)),
+            Err(_) => Err(anyhow!("server replied {}", status)),
+        }
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: RequestBuilder) -> anyhow::Result<T> {
+        Ok(Self::check(request.send().await?).await?.json().await?)
+    }
+
+    async fn get_task(&self, task_id: TaskId) -> anyhow::Result<TaskInfo> {
+        self.json(self.request(Client::get, &format!("tasks/{}", task_id)))
+            .await
+    }
+
+    // follow task events, print output as it comes if not saving to file
+    async fn wait(&self, task_id: TaskId, output: Option<PathBuf>) -> anyhow::Result<()> {
+        let resp = self
+            .request(Client::get, &format!("tasks/{}/events", task_id))
+            .send()
+            .await?;
+        let mut resp = Self::check(resp).await?;
+        let mut buffer = Vec::new();
+        let mut last_status = None;
+        while let Some(chunk) = resp.chunk().await? {
+            buffer.extend_from_slice(&chunk);
+            while let Some(end) = buffer.windows(2).position(|window| window == b"\n\n") {
+                let event: Vec<_> = buffer.drain(..end + 2).collect();
+                match parse_event(&String::from_utf8_lossy(&event)) {
+                    (Some("status"), data) => {
+                        let task: TaskInfo = serde_json::from_str(&data)?;
+                        if last_status == Some(task.status) && task.status != TaskStatus::Pending {
+                            continue;
+                        }
+                        match task.status {
+                            TaskStatus::Pending => eprintln!(
+                                "#{} pending, position in queue {}, expect to start in {} seconds, at most {} seconds",
+                                task_id,
+                                task.position.unwrap_or_default(),
+                                task.expected_wait_time.unwrap_or_default(),
+                                task.wait_time.unwrap_or_default()
+                            ),
+                            TaskStatus::Running => eprintln!("#{} running", task_id),
+                            TaskStatus::Finished => {
+                                eprintln!(
+                                    "#{} finished, exit status {}",
+                                    task_id,
+                                    task.exit_status
+                                        .map(|exit_status| exit_status.to_string())
+                                        .unwrap_or_else(|| String::from("none (killed)"))
+                                );
+                                show_results(&task.results);
+                            }
+                            TaskStatus::Canceled => bail!("#{} is canceled", task_id),
+                        }
+                        last_status = Some(task.status);
+                    }
+                    (Some("output"), data) if output.is_none() => {
+                        let text: String = serde_json::from_str(&data)?;
+                        let mut stdout = std::io::stdout();
+                        stdout.write_all(text.as_bytes())?;
+                        stdout.flush()?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if last_status != Some(TaskStatus::Finished) {
+            bail!("event stream of #{} closed before finishing", task_id);
+        }
+        if let Some(path) = output {
+            let resp = self
+                .request(Client::get, &format!("tasks/{}/output", task_id))
+                .send()
+                .await?;
+            fs::write(&path, Self::check(resp).await?.bytes().await?)?;
+            eprintln!("#{} output saved to {}", task_id, path.display());
+        }
+        Ok(())
+    }
+}
+
+// name and data of a server-sent event, comment lines (keep alive) ignored
+fn parse_event(event: &str) -> (Option<&str>, String) {
+    let mut name = None;
+    let mut data_list = Vec::new();
+    for line in event.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+            name = Some(value.trim_start());
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data_list.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+    (name, data_list.join("\n"))
+}
+
+fn show_results(results: &[TestResult]) {
+    if results.is_empty() {
+        return;
+    }
+    for result in results {
+        eprintln!(
+            "  TEST {}: {} ... {:?} ({}/{}pts)",
+            result.test, result.name, result.outcome, result.points, result.max_points
+        );
+    }
+    let (points, max_points) = report::score(results);
+    eprintln!("  total score {}/{}", points, max_points);
+}
+
+fn show(task: &TaskInfo) {
+    print!("#{} [{:?}] {}", task.id, task.status, task.preset);
+    if let Some(wait_time) = task.wait_time {
+        print!(
+            ", position in queue {}, wait about {} seconds, at most {} seconds",
+            task.position.unwrap_or_default(),
+            task.expected_wait_time.unwrap_or_default(),
+            wait_time
+        );
+    }
+    println!();
+}
+
+fn upload_form(dir: &Path) -> anyhow::Result<Form> {
+    let upload = pack(dir)?;
+    eprintln!("packed {} ({} bytes)", dir.display(), upload.len());
+    Ok(Form::new().part("upload", Part::bytes(upload).file_name("submit.tar.gz")))
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let runner = Runner {
+        client: Client::new(),
+        url: cli.url.trim_end_matches('/').to_string(),
+        token: cli.token,
+    };
+    match cli.command {
+        Command::Submit {
+            lab,
+            part,
+            test,
+            log_level,
+            check,
+            dir,
+            no_wait,
+            output,
+        } => {
+            // same fields as the submit form on home page
+            let preset = HashMap::from([
+                (":lab", lab),
+                (":test", format!("[{},{}]", part, test)),
+                (":log_level", log_level),
+                (":check", String::from(if check { "yes" } else { "no" })),
+            ]);
+            let form = upload_form(&dir)?.text("preset", serde_json::to_string(&preset)?);
+            let Submitted { id } = runner
+                .json(runner.request(Client::post, "tasks").multipart(form))
+                .await?;
+            eprintln!("submitted #{}", id);
+            if !no_wait {
+                runner.wait(id, output).await?;
+            }
+        }
+        Command::List => {
+            let task_list: Vec<TaskInfo> =
+                runner.json(runner.request(Client::get, "tasks")).await?;
+            for task in &task_list {
+                show(task);
+            }
+        }
+        Command::Status { id } => show(&runner.get_task(id).await?),
+        Command::Wait { id, output } => runner.wait(id, output).await?,
+        Command::Replace { id, dir } => {
+            let request = runner
+                .request(Client::put, &format!("tasks/{}/upload", id))
+                .multipart(upload_form(&dir)?);
+            show(&runner.json(request).await?);
+        }
+        Command::Cancel { id } => {
+            let request = runner.request(Client::post, &format!("tasks/{}/cancel", id));
+            show(&runner.json(request).await?);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,261 @@
+use anyhow::Context;
+use clap::Parser;
+use cs5223fet::app::{TaskId, OUTPUT_LIMIT};
+use cs5223fet::protocol::{self, FromWorker, ToWorker};
+use futures::prelude::*;
+use rmp_serde::{from_slice, to_vec_named};
+use std::fs;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command as StdCommand, Stdio};
+use std::str;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::spawn_blocking;
+use tokio::time::sleep;
+use tokio::{select, spawn};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+#[derive(Parser, Clone)]
+#[clap(about = "Run tasks from CS5223 test runner")]
+struct Args {
+    /// Server address, e.g. localhost:8080
+    #[clap(long, env = "CS5223FET_HOST")]
+    host: String,
+    /// Lab repository, copied for every task
+    #[clap(long, env = "CS5223FET_LAB_DIR", default_value = ".")]
+    lab_dir: PathBuf,
+    /// Output beyond is dropped
+    #[clap(long, default_value_t = OUTPUT_LIMIT)]
+    output_limit: usize,
+    /// Name shown in server log, host name by default
+    #[clap(long, env = "CS5223FET_WORKER_NAME")]
+    name: Option<String>,
+    /// Same as worker_secret of server
+    #[clap(long, env = "CS5223FET_WORKER_SECRET", hide_env_values = true)]
+    secret: String,
+}
+
+const OUTPUT_CHUNK: usize = 65536;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            fs::create_dir(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as _, buffer.len()) } != 0 {
+        return String::from("unknown");
+    }
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+// kill the whole group, so nothing started by the command outlives it
+fn kill_group(pid: u32) {
+    // fail if the group is gone already, which is fine
+    unsafe { libc::killpg(pid as _, libc::SIGKILL) };
+}
+
+async fn run_task(
+    args: Args,
+    task_id: TaskId,
+    command: String,
+    upload: Vec<u8>,
+    timeout: u64,
+    from_tx: mpsc::Sender<FromWorker>,
+    cancel_rx: oneshot::Receiver<()>,
+) -> anyhow::Result<()> {
+    let workspace = tempfile::tempdir()?;
+    let path = workspace.path().to_path_buf();
+    spawn_blocking(move || -> anyhow::Result<_> {
+        copy_dir(&args.lab_dir, &path)
+            .with_context(|| format!("cannot copy {}", args.lab_dir.display()))?;
+        fs::write(path.join("submit.tar.gz"), upload)?;
+        Ok(())
+    })
+    .await??;
+
+    let mut command_builder = StdCommand::new("sh");
+    command_builder
+        .arg("-c")
+        .arg(format!("exec 2>&1\n{}", command))
+        .current_dir(workspace.path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .process_group(0);
+    let mut child = Command::from(command_builder).spawn()?;
+    let pid = child.id().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+
+    let output_tx = from_tx.clone();
+    let output_limit = args.output_limit;
+    let reader = spawn(async move {
+        let mut buffer = vec![0; OUTPUT_CHUNK];
+        let mut pending = Vec::new(); // incomplete UTF-8 sequence at the end of last read
+        let mut output_length = 0;
+        loop {
+            let length = stdout.read(&mut buffer).await?;
+            if length == 0 {
+                break;
+            }
+            if output_length >= output_limit {
+                continue; // keep draining, or the command blocks on a full pipe
+            }
+            pending.extend_from_slice(&buffer[..length]);
+            let valid_length = match str::from_utf8(&pending) {
+                Ok(_) => pending.len(),
+                Err(error) if error.error_len().is_none() => error.valid_up_to(),
+                Err(_) => pending.len(), // invalid anyway, replaced below
+            };
+            let mut output = String::from_utf8_lossy(&pending[..valid_length]).into_owned();
+            pending.drain(..valid_length);
+            output_length += output.len();
+            if output_length >= output_limit {
+                output.push_str("\n*** Output truncated.\n");
+            }
+            output_tx
+                .send(FromWorker::Output { task_id, output })
+                .await?;
+        }
+        anyhow::Ok(())
+    });
+
+    let mut note = None;
+    let exit_status = select! {
+        exit_status = child.wait() => Some(exit_status?),
+        _ = sleep(Duration::from_secs(timeout)) => {
+            note = Some("\n*** Terminated on hard timeout.");
+            None
+        }
+        // also when connection is gone
+        _ = cancel_rx => {
+            note = Some("\n*** Canceled.");
+            None
+        }
+    };
+    kill_group(pid);
+    let exit_status = match exit_status {
+        Some(exit_status) => exit_status,
+        None => child.wait().await?,
+    };
+    reader.await??;
+    if let Some(note) = note {
+        let output = note.to_string();
+        from_tx.send(FromWorker::Output { task_id, output }).await?;
+    }
+    println!("[worker] task #{} {}", task_id, exit_status);
+    from_tx
+        .send(FromWorker::Finish {
+            task_id,
+            exit_status: exit_status.code(), // `None` if killed by signal
+        })
+        .await?;
+    Ok(())
+}
+
+// until connection is closed
+async fn serve(args: &Args, backoff: &mut Duration) -> anyhow::Result<()> {
+    let (websocket, _) = connect_async(format!("ws://{}/websocket", args.host)).await?;
+    println!("[worker] connected to {}", args.host);
+    *backoff = INITIAL_BACKOFF;
+    let (mut websocket_tx, mut websocket_rx) = websocket.split();
+    let hello = FromWorker::Hello {
+        version: protocol::VERSION,
+        secret: args.secret.clone(),
+        name: args.name.clone().unwrap_or_else(hostname),
+        capabilities: vec![String::from(protocol::CAPABILITY_CANCEL)],
+    };
+    websocket_tx
+        .send(Message::binary(to_vec_named(&hello)?))
+        .await?;
+    let (from_tx, mut from_rx) = mpsc::channel(16);
+    let mut running: Option<(TaskId, oneshot::Sender<()>)> = None;
+    loop {
+        select! {
+            message = websocket_rx.next() => {
+                let message = if let Some(message) = message {
+                    message?
+                } else {
+                    return Ok(());
+                };
+                if let Message::Close(Some(frame)) = &message {
+                    println!("[worker] closed by server: {}", frame.reason);
+                }
+                if !message.is_binary() {
+                    continue;
+                }
+                match from_slice::<ToWorker>(&message.into_data())? {
+                    ToWorker::Welcome { worker_id } => {
+                        println!("[worker] registered as worker {}", worker_id);
+                    }
+                    ToWorker::Run { task_id, command, upload, timeout } => {
+                        println!("[worker] task #{}: {}", task_id, command.trim());
+                        let (cancel_tx, cancel_rx) = oneshot::channel();
+                        running = Some((task_id, cancel_tx));
+                        let (args, from_tx) = (args.clone(), from_tx.clone());
+                        spawn(async move {
+                            let result = run_task(
+                                args, task_id, command, upload, timeout, from_tx.clone(), cancel_rx,
+                            )
+                            .await;
+                            if let Err(error) = result {
+                                println!("[worker] task #{} failed: {:#}", task_id, error);
+                                let output = format!("\n*** Worker error: {:#}", error);
+                                let _ = from_tx.send(FromWorker::Output { task_id, output }).await;
+                                let _ = from_tx.send(FromWorker::Finish { task_id, exit_status: None }).await;
+                            }
+                        });
+                    }
+                    ToWorker::Cancel { task_id } => {
+                        println!("[worker] cancel task #{}", task_id);
+                        if let Some((running_id, cancel_tx)) = running.take() {
+                            if running_id == task_id {
+                                let _ = cancel_tx.send(());
+                            } else {
+                                running = Some((running_id, cancel_tx));
+                            }
+                        }
+                    }
+                }
+            }
+            Some(from_worker) = from_rx.recv() => {
+                websocket_tx.send(Message::binary(to_vec_named(&from_worker)?)).await?;
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        match serve(&args, &mut backoff).await {
+            Ok(()) => println!("[worker] disconnected"),
+            Err(error) => println!("[worker] error: {:#}", error),
+        }
+        println!("[worker] reconnect in {:?}", backoff);
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
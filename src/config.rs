@@ -0,0 +1,269 @@
+use crate::queue::Policy;
+use anyhow::{anyhow, Context};
+use serde_derive::Deserialize;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const DEFAULT_PATH: &str = "cs5223fet.toml";
+
+// every field can be overridden by env var CS5223FET_<FIELD IN UPPER CASE>
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub port: u16,
+    pub url: String, // public url of the server, for oauth redirect
+    pub client_id: String,
+    pub secret: String,
+    pub worker_secret: String, // shared with workers, who receive uploads and report scores
+
+    pub store: String, // see `store::open`
+    pub fs_dir: PathBuf,
+    pub upload_limit: u64, // in byte
+
+    pub ping_interval: u64, // in second
+    pub grace_period: u64,  // in second, on top of task timeout
+    pub retry_limit: u32,
+    pub policy: Policy,
+
+    pub lab_dir: PathBuf,  // descriptions of labs other than demo
+    pub labs: Vec<String>, // open for submission
+
+    pub admins: Vec<String>, // GitHub logins of course staff
+    // who may submit besides admins, see `roster::Roster`
+    pub students: Vec<String>,
+    pub roster: PathBuf, // CSV file, empty for none
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 8080,
+            url: String::new(),
+            client_id: String::new(),
+            secret: String::new(),
+            worker_secret: String::new(),
+            store: "redis://localhost".to_string(),
+            fs_dir: PathBuf::from("_fs"),
+            upload_limit: 50_000,
+            ping_interval: 10,
+            grace_period: 5,
+            retry_limit: 2,
+            policy: Policy::Fifo,
+            lab_dir: PathBuf::from("labs"),
+            labs: vec!["lab4".to_string()],
+            admins: Vec::new(),
+            students: Vec::new(),
+            roster: PathBuf::new(),
+        }
+    }
+}
+
+fn override_with<T>(field: &mut T, name: &str) -> anyhow::Result<()>
+where
+    T: FromStr,
+    T::Err: Into<anyhow::Error>,
+{
+    let var = format!("CS5223FET_{}", name.to_uppercase());
+    if let Ok(value) = env::var(&var) {
+        *field = value
+            .parse()
+            .map_err(Into::into)
+            .with_context(|| format!("invalid value of {}: {:?}", var, value))?;
+    }
+    Ok(())
+}
+
+// comma separated
+fn override_list(field: &mut Vec<String>, name: &str) {
+    if let Ok(value) = env::var(format!("CS5223FET_{}", name.to_uppercase())) {
+        *field = value
+            .split(',')
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect();
+    }
+}
+
+impl Config {
+    // from file at CS5223FET_CONFIG, or cs5223fet.toml if present
+    pub fn load() -> anyhow::Result<Self> {
+        let (path, required) = match env::var("CS5223FET_CONFIG") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_PATH.to_string(), false),
+        };
+        let mut config = match fs::read_to_string(&path) {
+            Ok(content) => {
+                toml::from_str(&content).with_context(|| format!("invalid config file {}", path))?
+            }
+            Err(_) if !required => Self::default(),
+            Err(error) => {
+                return Err(error).with_context(|| format!("cannot read config file {}", path))
+            }
+        };
+
+        override_with(&mut config.port, "port")?;
+        override_with(&mut config.url, "url")?;
+        override_with(&mut config.client_id, "client_id")?;
+        override_with(&mut config.secret, "secret")?;
+        override_with(&mut config.worker_secret, "worker_secret")?;
+        override_with(&mut config.store, "store")?;
+        override_with(&mut config.fs_dir, "fs_dir")?;
+        override_with(&mut config.upload_limit, "upload_limit")?;
+        override_with(&mut config.ping_interval, "ping_interval")?;
+        override_with(&mut config.grace_period, "grace_period")?;
+        override_with(&mut config.retry_limit, "retry_limit")?;
+        override_with(&mut config.policy, "policy")?;
+        override_with(&mut config.lab_dir, "lab_dir")?;
+        override_list(&mut config.labs, "labs");
+        override_list(&mut config.admins, "admins");
+        override_list(&mut config.students, "students");
+        override_with(&mut config.roster, "roster")?;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("url", &self.url),
+            ("client_id", &self.client_id),
+            ("secret", &self.secret),
+            ("worker_secret", &self.worker_secret),
+        ] {
+            if value.is_empty() {
+                return Err(anyhow!("{} is not configured", name));
+            }
+        }
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(anyhow!("url must start with http:// or https://"));
+        }
+        if self.upload_limit == 0 {
+            return Err(anyhow!("upload_limit must be positive"));
+        }
+        if self.ping_interval == 0 {
+            return Err(anyhow!("ping_interval must be positive"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // env vars are shared by all tests in process
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    const REQUIRED: &str = r#"
+url = "https://fet.example.com"
+client_id = "id"
+secret = "secret"
+worker_secret = "worker secret"
+"#;
+
+    fn clear_env() {
+        for (var, _) in env::vars() {
+            if var.starts_with("CS5223FET_") {
+                env::remove_var(var);
+            }
+        }
+    }
+
+    // from a config file of `content` and env vars `vars`
+    fn load_with(content: &str, vars: &[(&str, &str)]) -> anyhow::Result<Config> {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cs5223fet.toml");
+        fs::write(&path, content).unwrap();
+        clear_env();
+        env::set_var("CS5223FET_CONFIG", &path);
+        for (var, value) in vars {
+            env::set_var(var, value);
+        }
+        let config = Config::load();
+        clear_env();
+        config
+    }
+
+    #[test]
+    fn default_fields() {
+        let config = load_with(REQUIRED, &[]).unwrap();
+        assert_eq!(config.url, "https://fet.example.com");
+        assert_eq!(config.worker_secret, "worker secret");
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.store, "redis://localhost");
+        assert_eq!(config.fs_dir, PathBuf::from("_fs"));
+        assert_eq!(config.retry_limit, 2);
+        assert_eq!(config.policy, Policy::Fifo);
+        assert_eq!(config.labs, ["lab4"]);
+        assert!(config.admins.is_empty());
+        assert_eq!(config.roster, PathBuf::new());
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let content = format!(
+            "{}port = 1234\npolicy = \"fifo\"\nlabs = [\"lab3\"]\n",
+            REQUIRED
+        );
+        let config = load_with(
+            &content,
+            &[
+                ("CS5223FET_PORT", "4321"),
+                ("CS5223FET_POLICY", "fair-share"),
+                ("CS5223FET_LABS", "demo, ,lab4"),
+                ("CS5223FET_ADMINS", ""),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.port, 4321);
+        assert_eq!(config.policy, Policy::FairShare);
+        assert_eq!(config.labs, ["demo", "lab4"]);
+        assert!(config.admins.is_empty());
+
+        // required fields may come from env only
+        let config = load_with(
+            "",
+            &[
+                ("CS5223FET_URL", "http://localhost:8080"),
+                ("CS5223FET_CLIENT_ID", "id"),
+                ("CS5223FET_SECRET", "secret"),
+                ("CS5223FET_WORKER_SECRET", "worker secret"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.url, "http://localhost:8080");
+
+        let error = load_with(REQUIRED, &[("CS5223FET_PORT", "http")]).unwrap_err();
+        assert!(format!("{:#}", error).contains("CS5223FET_PORT"));
+        assert!(load_with(REQUIRED, &[("CS5223FET_POLICY", "lottery")]).is_err());
+    }
+
+    #[test]
+    fn invalid_config() {
+        let without_worker_secret = REQUIRED.replace("worker_secret", "# worker_secret");
+        let error = load_with(&without_worker_secret, &[]).unwrap_err();
+        assert_eq!(error.to_string(), "worker_secret is not configured");
+        let error = load_with(REQUIRED, &[("CS5223FET_URL", "fet.example.com")]).unwrap_err();
+        assert_eq!(error.to_string(), "url must start with http:// or https://");
+        for field in [
+            "upload_limit = 0",
+            "ping_interval = 0",
+            "unknown = 1",
+            "port = \"80\"",
+        ] {
+            let content = format!("{}{}\n", REQUIRED, field);
+            assert!(load_with(&content, &[]).is_err(), "{}", field);
+        }
+
+        // a named config file must exist
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        clear_env();
+        env::set_var("CS5223FET_CONFIG", "/nonexistent/cs5223fet.toml");
+        assert!(Config::load().is_err());
+        clear_env();
+    }
+}
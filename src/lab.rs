@@ -0,0 +1,112 @@
+use crate::preset::Preset as PresetTrait;
+use crate::presets::{data, demo};
+use anyhow::anyhow;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+// the built-in demo lab, all others are described in data files
+pub const DEMO: &str = "demo";
+
+pub fn exists(lab: &str) -> bool {
+    lab == DEMO || data::get_lab(lab).is_some()
+}
+
+pub fn render_html(lab: &str) -> String {
+    if lab == DEMO {
+        demo::Preset::render_html()
+    } else {
+        data::get_lab(lab).unwrap().render_html()
+    }
+}
+
+// preset of any lab, so one server can take submissions of several labs and
+// still show history of past labs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Preset {
+    Demo(demo::Preset),
+    Data(data::Preset),
+}
+
+impl Preset {
+    pub fn lab(&self) -> &str {
+        match self {
+            Self::Demo(_) => DEMO,
+            Self::Data(preset) => preset.lab(),
+        }
+    }
+
+    // `None` for demo, which has no parts
+    pub fn part_test(&self) -> Option<(u32, u32)> {
+        match self {
+            Self::Demo(_) => None,
+            Self::Data(preset) => Some((preset.part(), preset.test())),
+        }
+    }
+}
+
+impl TryFrom<HashMap<String, String>> for Preset {
+    type Error = anyhow::Error;
+    fn try_from(form: HashMap<String, String>) -> anyhow::Result<Self> {
+        match &**form.get(":lab").ok_or(anyhow!("no lab field"))? {
+            DEMO => Ok(Self::Demo(form.try_into()?)),
+            lab => Ok(Self::Data(data::Preset::from_form(lab, &form)?)),
+        }
+    }
+}
+
+impl Display for Preset {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Demo(preset) => write!(f, "Demo: {}", preset),
+            Self::Data(preset) => write!(
+                f,
+                "{}: {}",
+                data::get_lab(preset.lab()).unwrap().name,
+                preset
+            ),
+        }
+    }
+}
+
+impl PresetTrait for Preset {
+    fn render_html() -> String {
+        [DEMO.to_string()]
+            .into_iter()
+            .chain(data::lab_list())
+            .map(|lab| render_html(&lab))
+            .collect()
+    }
+    fn get_command(&self) -> String {
+        match self {
+            Self::Demo(preset) => preset.get_command(),
+            Self::Data(preset) => preset.get_command(),
+        }
+    }
+    fn get_timeout(&self) -> u64 {
+        match self {
+            Self::Demo(preset) => preset.get_timeout(),
+            Self::Data(preset) => preset.get_timeout(),
+        }
+    }
+
+    // keep the preset JSON in the same shape as a single lab server does, and
+    // record lab next to it
+    fn to_record(&self) -> Vec<(&'static str, String)> {
+        let preset = match self {
+            Self::Demo(preset) => to_string(preset),
+            Self::Data(preset) => to_string(preset),
+        };
+        vec![("lab", self.lab().to_string()), ("preset", preset.unwrap())]
+    }
+    fn from_record(record: &HashMap<String, String>) -> anyhow::Result<Self> {
+        // tasks from before lab is recorded were all submitted to lab 4
+        let lab = record.get("lab").map(|lab| &**lab).unwrap_or("lab4");
+        let preset = record.get("preset").ok_or(anyhow!("no preset in record"))?;
+        match lab {
+            DEMO => Ok(Self::Demo(from_str(preset)?)),
+            lab => Ok(Self::Data(data::Preset::from_json(lab, preset)?)),
+        }
+    }
+}
@@ -1,21 +1,28 @@
 use std::future::Future;
 use warp::reject::Reject;
 
+pub mod api;
 pub mod app;
+pub mod config;
+pub mod lab;
 pub mod oauth;
 pub mod preset;
 pub mod presets {
+    pub mod data;
     pub mod demo;
-    pub mod lab3;
-    pub mod lab4;
 }
+pub mod protocol;
+pub mod queue;
+pub mod report;
+pub mod roster;
+pub mod store;
 
 #[derive(Debug)]
 struct AnyHowError(pub anyhow::Error);
 impl Reject for AnyHowError {}
 
-pub fn with_anyhow<T>(
+pub async fn with_anyhow<T>(
     inner: impl Future<Output = anyhow::Result<T>>,
-) -> impl Future<Output = Result<T, warp::Rejection>> {
-    async move { inner.await.map_err(|error| AnyHowError(error).into()) }
+) -> Result<T, warp::Rejection> {
+    inner.await.map_err(|error| AnyHowError(error).into())
 }
@@ -1,19 +1,21 @@
 use anyhow::anyhow;
 use bytes::BufMut;
-use cs5223fet::app::{App, Task, TaskId, TaskStatus};
+use cs5223fet::api;
+use cs5223fet::app::{Actor, App, DispatchMode, Task, TaskId, TaskStatus};
+use cs5223fet::config::Config;
+use cs5223fet::lab::{self, Preset};
 use cs5223fet::oauth::OAuth;
-use cs5223fet::preset::Preset as _;
+use cs5223fet::presets::data;
+use cs5223fet::report::{self, TestResult};
+use cs5223fet::store;
 use cs5223fet::with_anyhow;
 use futures::prelude::*;
 use serde_json::from_slice;
 use std::collections::HashMap;
-use std::env;
 use std::sync::Arc;
 use warp::multipart::FormData;
 use warp::{reply, Filter};
 
-use cs5223fet::presets::lab4::Preset;
-
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
     fn universal() -> &'static str {
@@ -25,14 +27,64 @@ async fn main() -> anyhow::Result<()> {
     fn home_prompt() -> String {
         format!(r#"{}<a href="/">Home</a>"#, universal())
     }
+    // in place of the submit form
+    fn not_enrolled_prompt(user_id: &str) -> String {
+        format!(
+            r#"<p>GitHub ID {} is not enrolled in this course.</p>
+<p>If you are taking the course, ask course staff to add your GitHub ID to the
+roster.</p>"#,
+            user_id
+        )
+    }
 
-    let oauth = Arc::new(OAuth::new()?);
-    let app = Arc::new(App::<Preset>::new().await?);
+    let config = Config::load()?;
+    data::load(&config.lab_dir)?;
+    for lab in &config.labs {
+        if !lab::exists(lab) {
+            return Err(anyhow!("lab {:?} is open but not described", lab));
+        }
+    }
+    let store = store::open(&config).await?;
+    let oauth = Arc::new(OAuth::new(&config, store.clone()).await?);
+    let app = Arc::new(App::<Preset>::new(store, &config).await?);
 
     let home_app = app.clone();
-    let route = oauth.user_id().and(warp::path::end()).then(move |id| {
+    let home_oauth = oauth.clone();
+    let upload_limit = config.upload_limit;
+    let submit_form: String = config
+        .labs
+        .iter()
+        .map(|lab| {
+            format!(
+                r#"
+<form class="submit-form" action="/task/submit" method="post" enctype="multipart/form-data">
+    <input type="file" name="upload">
+    <input class="submit-preset" type="hidden" name="preset">
+    <input type="hidden" name=":lab" value="{}">
+    {}
+    <button class="submit-button" type="submit" disabled>Submit</button>
+</form>
+"#,
+                lab,
+                lab::render_html(lab)
+            )
+        })
+        .collect();
+    let route = oauth.user_id().and(warp::path::end()).then(move |id: String| {
         let home_app = home_app.clone();
+        let submit_form = submit_form.clone();
+        let home_oauth = home_oauth.clone();
+        let admin_prompt = if home_oauth.is_admin(&id) {
+            r#" <a href="/admin">Admin</a>"#
+        } else {
+            ""
+        };
         async move {
+            let submit_form = if home_oauth.is_enrolled(&id).await {
+                submit_form
+            } else {
+                not_enrolled_prompt(&id)
+            };
             let task_navigation: Vec<_> = home_app
                 .data
                 .read()
@@ -50,23 +102,17 @@ async fn main() -> anyhow::Result<()> {
                 r#"
 {}
 <p>CS5223 Slow and Hard Test<sup>beta</sup></p>
-<p>System status: {} GitHub ID: {}</p>
-<form id="submit-form" action="/task/submit" method="post" enctype="multipart/form-data">
-    <input type="file" name="upload">
-    <input id="submit-preset" type="hidden" name="preset">
-    {}
-    <button id="submit-button" type="submit" disabled>Submit</button>
-</form>
+<p>System status: {} GitHub ID: {} <a href="/history">History</a> <a href="/settings">API tokens</a>{}</p>
+{}
 <script>
-let form;
 function start() {{
-    form = document.querySelector('#submit-form');
-    form.addEventListener('submit', onSubmit);
-    
-    const button = document.querySelector('#submit-button');
-    button.disabled = false;
+    for (const form of document.querySelectorAll('.submit-form')) {{
+        form.addEventListener('submit', onSubmit);
+        form.querySelector('.submit-button').disabled = false;
+    }}
 }}
 function onSubmit(e) {{
+    const form = e.target;
     const preset = new Object;
     for (let child of form.childNodes) {{
         if (!child.name || !child.name.startsWith(':')) {{
@@ -74,7 +120,7 @@ function onSubmit(e) {{
         }}
         preset[child.name] = child.value;
     }}
-    const presetNode = document.querySelector('#submit-preset');
+    const presetNode = form.querySelector('.submit-preset');
     presetNode.value = JSON.stringify(preset);
 }}
 window.addEventListener('DOMContentLoaded', start);
@@ -85,26 +131,220 @@ window.addEventListener('DOMContentLoaded', start);
     one GitHub ID.</li>
     <li>You can replace upload file for a pending task, but you are not allowed 
     to change to another set of settings.</li>
-    <li>Upload file size limits to about 50KB.</li>
+    <li>Upload file size limits to about {}KB.</li>
 </ul>
 "#,
                 universal(),
                 home_app.status.read().await,
                 id,
-                Preset::render_html(),
-                task_navigation.join(" ")
+                admin_prompt,
+                submit_form,
+                task_navigation.join(" "),
+                upload_limit / 1000
             ))
         }
     });
 
-    let submit_app = app.clone();
+    // filter of the history page, a task matches a part or test if it either
+    // selects it or runs it as part of a suite
+    struct HistoryFilter {
+        lab: Option<String>,
+        part: Option<u32>,
+        test: Option<u32>,
+    }
+    impl HistoryFilter {
+        fn new(query: &HashMap<String, String>) -> anyhow::Result<Self> {
+            let field = |name| query.get(name).filter(|value: &&String| !value.is_empty());
+            Ok(Self {
+                lab: field("lab").cloned(),
+                part: field("part").map(|part| part.parse()).transpose()?,
+                test: field("test").map(|test| test.parse()).transpose()?,
+            })
+        }
+        fn matches_result(&self, result: &TestResult) -> bool {
+            match (self.part, self.test) {
+                (None, _) => true,
+                (Some(part), None) => result.test.starts_with(&format!("{}.", part)),
+                (Some(part), Some(test)) => result.test == format!("{}.{}", part, test),
+            }
+        }
+        fn matches_task(&self, task: &Task<Preset>) -> bool {
+            if self
+                .lab
+                .as_deref()
+                .is_some_and(|lab| lab != task.preset.lab())
+            {
+                return false;
+            }
+            let part = match self.part {
+                Some(part) => part,
+                None => return true,
+            };
+            let selected = task
+                .preset
+                .part_test()
+                .is_some_and(|(task_part, task_test)| {
+                    task_part == part && self.test.is_none_or(|test| test == task_test)
+                });
+            selected
+                || task
+                    .results
+                    .iter()
+                    .any(|result| self.matches_result(result))
+        }
+    }
+    fn time_html(time: Option<u64>) -> String {
+        time.map(|time| format!(r#"<time data-time="{0}">{0}</time>"#, time))
+            .unwrap_or_default()
+    }
+    // show `time_html` in browser's time zone
+    fn time_script() -> &'static str {
+        r#"
+<script>
+for (const time of document.querySelectorAll('time[data-time]')) {
+    time.textContent = new Date(time.dataset.time * 1000).toLocaleString();
+}
+</script>
+"#
+    }
+
+    let history_app = app.clone();
+    let history_labs = config.labs.clone();
     let route = route.or(oauth
         .user_id()
-        .and(warp::path!("task" / "submit"))
+        .and(warp::path!("history"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(move |user_id: String, query| {
+            let history_app = history_app.clone();
+            let history_labs = history_labs.clone();
+            with_anyhow(async move {
+                let filter = HistoryFilter::new(&query)?;
+                let task_list = history_app
+                    .data
+                    .read()
+                    .await
+                    .user_table
+                    .get(&user_id)
+                    .cloned()
+                    .unwrap_or_default();
+                let mut history = Vec::new();
+                for task_id in task_list {
+                    // unparseable record, e.g., of a removed lab, should not
+                    // hide the rest of history
+                    let task = if let Ok(task) = history_app.get_task(task_id).await {
+                        task
+                    } else {
+                        continue;
+                    };
+                    if filter.matches_task(&task) {
+                        history.push((task_id, task));
+                    }
+                }
+
+                let rows: String = history
+                    .iter()
+                    .rev()
+                    .map(|(task_id, task)| {
+                        let duration = match (task.start_time(), task.finish_time()) {
+                            (Some(start_time), Some(finish_time)) if task.status == TaskStatus::Finished => {
+                                format!("{}s", finish_time.saturating_sub(start_time))
+                            }
+                            _ => String::new(),
+                        };
+                        let score = if task.results.is_empty() {
+                            String::new()
+                        } else {
+                            let (points, max_points) = report::score(&task.results);
+                            format!("{}/{}", points, max_points)
+                        };
+                        format!(
+                            r#"<tr><td><a href="/task/{0}">#{0}</a></td><td>{1}</td><td>{2:?}</td><td>{3}</td><td>{4}</td><td>{5}</td><td>{6}</td><td>{7}</td></tr>"#,
+                            task_id,
+                            task.preset,
+                            task.status,
+                            time_html(task.submit_time()),
+                            time_html(task.start_time()),
+                            time_html(task.finish_time()),
+                            duration,
+                            score
+                        )
+                    })
+                    .collect();
+                let results: Vec<Vec<_>> = history
+                    .iter()
+                    .map(|(_, task)| {
+                        task.results
+                            .iter()
+                            .filter(|result| filter.matches_result(result))
+                            .cloned()
+                            .collect()
+                    })
+                    .collect();
+                let trend_list: Vec<_> = history
+                    .iter()
+                    .zip(&results)
+                    .map(|((task_id, _), results)| (*task_id, &results[..]))
+                    .collect();
+                let lab_options: String = [""]
+                    .into_iter()
+                    .chain(history_labs.iter().map(|lab| &**lab))
+                    .map(|lab| {
+                        format!(
+                            r#"<option value="{0}"{1}>{2}</option>"#,
+                            lab,
+                            if filter.lab.as_deref().unwrap_or_default() == lab {
+                                r#" selected="selected""#
+                            } else {
+                                ""
+                            },
+                            if lab.is_empty() { "All labs" } else { lab }
+                        )
+                    })
+                    .collect();
+                let number = |value: Option<u32>| value.map(|n| n.to_string()).unwrap_or_default();
+                Ok(reply::html(format!(
+                    r#"
+{}
+<form action="/history" method="get">
+    <select name="lab">{}</select>
+    <label for="part">Part:</label>
+    <input type="number" name="part" id="part" min="0" value="{}">
+    <label for="test">Test:</label>
+    <input type="number" name="test" id="test" min="1" value="{}">
+    <button type="submit">Filter</button>
+</form>
+{}
+<table id="history">
+    <tr><th>Task</th><th>Preset</th><th>Status</th><th>Submitted</th><th>Started</th><th>Finished</th><th>Duration</th><th>Score</th></tr>
+    {}
+</table>
+{}
+<ul>
+    <li>A part or test filter also matches suites that run it, and only its
+    results are counted in the pass rate.</li>
+    <li>Tasks submitted before timestamps are recorded have no time shown.</li>
+</ul>
+"#,
+                    home_prompt(),
+                    lab_options,
+                    number(filter.part),
+                    number(filter.test),
+                    report::render_trend_html(&trend_list),
+                    rows,
+                    time_script()
+                )))
+            })
+        }));
+
+    let submit_app = app.clone();
+    let submit_labs = config.labs.clone();
+    let route = route.or(warp::path!("task" / "submit")
         .and(warp::post())
-        .and(warp::multipart::form().max_length(50_000))
+        .and(oauth.student_id())
+        .and(warp::multipart::form().max_length(config.upload_limit))
         .and_then(move |id, form: FormData| {
             let submit_app = submit_app.clone();
+            let submit_labs = submit_labs.clone();
             with_anyhow(async move {
                 let mut form: HashMap<_, _> = form
                     .map_ok(|part| (part.name().to_string(), part.stream()))
@@ -120,6 +360,9 @@ window.addEventListener('DOMContentLoaded', start);
                     .await?;
                 let preset: HashMap<String, String> = from_slice(&preset)?;
                 let preset: Preset = preset.try_into()?;
+                if !submit_labs.iter().any(|lab| lab == preset.lab()) {
+                    return Err(anyhow!("{} is not open for submission", preset.lab()));
+                }
                 let upload = form
                     .remove("upload")
                     .ok_or(anyhow!("no upload in submission"))?
@@ -138,6 +381,10 @@ window.addEventListener('DOMContentLoaded', start);
                         preset,
                         upload,
                         status: TaskStatus::Pending,
+                        retry: 0,
+                        exit_status: None,
+                        results: Vec::new(),
+                        transitions: Vec::new(), // set on pushing
                     })
                     .await?;
                 Ok(reply::html(format!(
@@ -159,45 +406,166 @@ window.addEventListener('DOMContentLoaded', start);
                 let task = task_app.get_task(task_id).await?;
                 let output_prompt = if task.status == TaskStatus::Finished {
                     format!(r#"<a href="/task/{0}/output/{0}">output</a>"#, task_id)
+                } else if task.status == TaskStatus::Canceled
+                    && task_app.get_output(task_id).await.is_ok()
+                {
+                    // worker is gone or timed out during running
+                    format!(
+                        r#"<a href="/task/{0}/output/{0}">partial output</a>"#,
+                        task_id
+                    )
                 } else {
-                    format!("")
+                    String::new()
+                };
+                let transition_list: String = task
+                    .transitions
+                    .iter()
+                    .map(|transition| {
+                        format!("<li>{} {}</li>", time_html(Some(transition.time)), transition)
+                    })
+                    .collect();
+                let exit_prompt = match (task.status, task.exit_status) {
+                    (TaskStatus::Finished, Some(exit_status)) => {
+                        format!(", exit status: {}", exit_status)
+                    }
+                    (TaskStatus::Finished, None) => String::from(", killed"),
+                    _ => String::new(),
                 };
                 let wait_time_prompt = if task.status == TaskStatus::Pending {
                     format!(
-                        r", maximum waiting time: {:?}",
+                        r", position in queue: {}, expected waiting time: {:?}, maximum waiting time: {:?}",
+                        task_app.get_position(task_id).await.unwrap_or_default(),
+                        task_app.get_expected_wait_time(task_id).await,
                         task_app.get_wait_time(task_id).await
                     )
                 } else {
-                    format!("")
+                    String::new()
                 };
-                let edit_prompt = if task.status == TaskStatus::Pending {
+                let retry_prompt = if task.retry > 0 {
+                    format!(
+                        ", requeued {} time(s) because worker disconnected",
+                        task.retry
+                    )
+                } else {
+                    String::new()
+                };
+                let replace_prompt = if task.status == TaskStatus::Pending {
                     format!(
                         r#"
-<form action="/task/{0}/replace" method="post" enctype="multipart/form-data">
+<form id="task-replace" action="/task/{0}/replace" method="post" enctype="multipart/form-data">
     <input type="file" name="upload">
     <button type="submit">Replace upload</button>
-</form>
-<form action="/task/{0}/cancel" method="post">
+</form>"#,
+                        task_id
+                    )
+                } else {
+                    String::new()
+                };
+                let edit_prompt = if task.status == TaskStatus::Pending
+                    || task.status == TaskStatus::Running
+                {
+                    format!(
+                        r#"
+<div id="task-edit">
+{}
+<form action="/task/{}/cancel" method="post">
     <button type="submit">Cancel</button>
 </form>
+</div>
+"#,
+                        replace_prompt, task_id
+                    )
+                } else {
+                    String::new()
+                };
+                // keep status and output updated until the task is done
+                let live_prompt = if task.status == TaskStatus::Pending
+                    || task.status == TaskStatus::Running
+                {
+                    format!(
+                        r#"
+<pre id="task-output"></pre>
+<script>
+// same as the table rendered for a finished task
+function showResults(results) {{
+    if (results.length === 0) {{
+        return;
+    }}
+    const table = document.createElement('table');
+    table.id = 'task-results';
+    table.insertRow().innerHTML = '<th>Test</th><th>Name</th><th>Result</th><th>Points</th><th>Time</th>';
+    let points = 0, maxPoints = 0;
+    for (const result of results) {{
+        const row = table.insertRow();
+        for (const text of [result.test, result.name, result.outcome,
+            `${{result.points}}/${{result.max_points}}`,
+            result.duration_ms !== null ? `${{(result.duration_ms / 1000).toFixed(3)}}s` : '']) {{
+            row.insertCell().textContent = text;
+        }}
+        points += result.points;
+        maxPoints += result.max_points;
+    }}
+    const total = document.createElement('p');
+    total.textContent = `Total score: ${{points}}/${{maxPoints}}`;
+    document.getElementById('task-output').before(table, total);
+}}
+const source = new EventSource('/api/v1/tasks/{0}/events');
+let lastStatus = null;
+source.addEventListener('status', e => {{
+    const info = JSON.parse(e.data);
+    if (info.status === 'Running' && lastStatus !== 'Running') {{
+        document.getElementById('task-output').textContent = '';
+    }}
+    lastStatus = info.status;
+    let text = info.status;
+    if (info.status === 'Finished') {{
+        text += info.exit_status !== null ? `, exit status: ${{info.exit_status}}` : ', killed';
+    }}
+    if (info.wait_time !== null) {{
+        text += `, position in queue: ${{info.position}}, expected waiting time: ${{info.expected_wait_time}}s, maximum waiting time: ${{info.wait_time}}s`;
+    }}
+    if (info.retry > 0) {{
+        text += `, requeued ${{info.retry}} time(s) because worker disconnected`;
+    }}
+    document.getElementById('task-status').textContent = text;
+    if (info.status !== 'Pending') {{
+        document.getElementById('task-replace')?.remove();
+    }}
+    if (info.status === 'Finished' || info.status === 'Canceled') {{
+        document.getElementById('task-edit')?.remove();
+        source.close();
+    }}
+    if (info.status === 'Finished') {{
+        document.getElementById('task-status').insertAdjacentHTML('afterend',
+            '<a href="/task/{0}/output/{0}">output</a>');
+        showResults(info.results);
+    }}
+}});
+source.addEventListener('output', e => {{
+    document.getElementById('task-output').textContent += JSON.parse(e.data);
+}});
+</script>
 "#,
                         task_id
                     )
                 } else {
-                    format!("")
+                    String::new()
                 };
                 Ok(reply::html(format!(
                     r#"
 {}
 <p>#{} {}</p>
-<p>{:?}{}</p>
+<p id="task-status">{:?}{}{}{}</p>
+<ul id="task-transitions">{}</ul>
+{}
+{}
+{}
 {}
 {}
 <ul>
-    <li>Server does not store upload file on disk, so upon system failure it has
-    to cancel pending/running task if upload file is lost. Sorry for 
-    inconvenience if that happens.</li>
-    <li>Test output is trimmed and only the last 10MB is available for 
+    <li>Upload file is kept on server until the task finishes, so a pending or
+    running task is queued again after server restarts.</li>
+    <li>Test output is trimmed and only the first 10MB is available for 
     downloading.</li>
 </ul>
 "#,
@@ -205,9 +573,15 @@ window.addEventListener('DOMContentLoaded', start);
                     task_id,
                     task.preset,
                     task.status,
+                    exit_prompt,
                     wait_time_prompt,
+                    retry_prompt,
+                    transition_list,
                     output_prompt,
-                    edit_prompt
+                    report::render_html(&task.results),
+                    edit_prompt,
+                    live_prompt,
+                    time_script()
                 )))
             })
         },
@@ -218,7 +592,7 @@ window.addEventListener('DOMContentLoaded', start);
         .user_id()
         .and(warp::path!("task" / TaskId / "replace"))
         .and(warp::post())
-        .and(warp::multipart::form().max_length(50_000))
+        .and(warp::multipart::form().max_length(config.upload_limit))
         .and_then(move |user_id: String, task_id, form: FormData| {
             let replace_app = replace_app.clone();
             with_anyhow(async move {
@@ -264,7 +638,9 @@ window.addEventListener('DOMContentLoaded', start);
                     return Err(anyhow!("cancel rejected"));
                 }
 
-                cancel_app.cancel_task(task_id).await?;
+                cancel_app
+                    .cancel_task(task_id, Actor::User(user_id))
+                    .await?;
                 Ok(reply::html(format!(
                     "{}<p> Task #{} canceled.</p>",
                     home_prompt(),
@@ -276,28 +652,297 @@ window.addEventListener('DOMContentLoaded', start);
     let output_app = app.clone();
     let route = route.or(oauth
         .user_id()
-        .and(warp::path("task"))
-        .and(warp::path::param())
-        .and(warp::path("output"))
-        .and(warp::path::peek())
-        .and_then(move |user_id: String, task_id, peek: warp::path::Peek| {
+        .and(warp::path!("task" / TaskId / "output" / TaskId))
+        .and_then(move |user_id: String, task_id, peek: TaskId| {
             let output_app = output_app.clone();
             with_anyhow(async move {
-                let peek: TaskId = peek.as_str().parse()?;
                 if peek != task_id {
                     return Err(anyhow!("invalid url"));
                 }
                 if !output_app.allow_access(&user_id, task_id).await {
                     return Err(anyhow!("task id not accessible"));
                 }
-                if output_app.get_task(task_id).await?.status != TaskStatus::Finished {
+                // partial output for running and canceled task
+                if output_app.get_task(task_id).await?.status == TaskStatus::Pending {
                     return Err(anyhow!("no available output"));
                 }
-                Ok(())
+                Ok(reply::with_header(
+                    output_app.get_output(task_id).await?,
+                    "Content-Type",
+                    "text/plain; charset=utf-8",
+                ))
             })
-        })
-        .untuple_one()
-        .and(warp::fs::dir("_fs/output")));
+        }));
+
+    let settings_oauth = oauth.clone();
+    let route = route.or(oauth
+        .user_id()
+        .and(warp::path!("settings"))
+        .and(warp::get())
+        .then(move |user_id: String| {
+            let settings_oauth = settings_oauth.clone();
+            async move {
+                let token_list: String = settings_oauth
+                    .token_list(&user_id)
+                    .await
+                    .into_iter()
+                    .map(|name| {
+                        format!(
+                            r#"
+<li><form action="/settings/token/revoke" method="post">
+    {0}
+    <input type="hidden" name="name" value="{0}">
+    <button type="submit">Revoke</button>
+</form></li>"#,
+                            name
+                        )
+                    })
+                    .collect();
+                reply::html(format!(
+                    r#"
+{}
+<p>API tokens of {}</p>
+<ul>{}</ul>
+<form action="/settings/token" method="post">
+    <input type="text" name="name" placeholder="token name">
+    <button type="submit">Create</button>
+</form>
+<ul>
+    <li>Send token as <code>Authorization: Bearer &lt;token&gt;</code> header
+    to access /api/v1 without browser, e.g., with the <code>cs5223fet</code>
+    command line client.</li>
+    <li>Token is shown only once after creation. Revoke and create a new one
+    if it is lost.</li>
+</ul>
+"#,
+                    home_prompt(),
+                    user_id,
+                    token_list
+                ))
+            }
+        }));
+
+    let create_oauth = oauth.clone();
+    let route = route.or(oauth
+        .user_id()
+        .and(warp::path!("settings" / "token"))
+        .and(warp::post())
+        .and(warp::body::form())
+        .and_then(move |user_id: String, form: HashMap<String, String>| {
+            let create_oauth = create_oauth.clone();
+            with_anyhow(async move {
+                let name = form.get("name").ok_or(anyhow!("no name field"))?;
+                let token = create_oauth.create_token(&user_id, name).await?;
+                Ok(reply::html(format!(
+                    r#"{}<p>Token {} created: <code>{}</code></p><a href="/settings">Settings</a>"#,
+                    home_prompt(),
+                    name,
+                    token
+                )))
+            })
+        }));
+
+    let revoke_oauth = oauth.clone();
+    let route = route.or(oauth
+        .user_id()
+        .and(warp::path!("settings" / "token" / "revoke"))
+        .and(warp::post())
+        .and(warp::body::form())
+        .and_then(move |user_id: String, form: HashMap<String, String>| {
+            let revoke_oauth = revoke_oauth.clone();
+            with_anyhow(async move {
+                let name = form.get("name").ok_or(anyhow!("no name field"))?;
+                revoke_oauth.revoke_token(&user_id, name).await?;
+                Ok(reply::html(format!(
+                    r#"{}<p>Token {} revoked.</p><a href="/settings">Settings</a>"#,
+                    home_prompt(),
+                    name
+                )))
+            })
+        }));
+
+    let admin_oauth = oauth.clone();
+    let admin_app = app.clone();
+    let route = route.or(warp::path!("admin")
+        .and(warp::get())
+        .and(oauth.admin_id())
+        .then(move |user_id: String| {
+            let admin_app = admin_app.clone();
+            let admin_oauth = admin_oauth.clone();
+            async move {
+                let mut task_list = String::new();
+                for (task_id, task) in admin_app.outstanding_tasks().await {
+                    let (position, wait_time) = if task.status == TaskStatus::Pending {
+                        (
+                            admin_app
+                                .get_position(task_id)
+                                .await
+                                .map(|position| position.to_string())
+                                .unwrap_or_default(),
+                            format!(
+                                "{:?} / {:?}",
+                                admin_app.get_expected_wait_time(task_id).await,
+                                admin_app.get_wait_time(task_id).await
+                            ),
+                        )
+                    } else {
+                        Default::default()
+                    };
+                    let front_prompt = if task.status == TaskStatus::Pending {
+                        format!(
+                            r#"<form action="/admin/task/{}/front" method="post"><button type="submit">Move to front</button></form>"#,
+                            task_id
+                        )
+                    } else {
+                        String::new()
+                    };
+                    let student_prompt = match admin_oauth.get_student(&task.user_id).await {
+                        Some(student) if !student.student_id.is_empty() => {
+                            format!(" ({}, section {})", student.student_id, student.section)
+                        }
+                        _ => String::new(),
+                    };
+                    task_list += &format!(
+                        r#"
+<tr>
+    <td>#{0}</td><td>{1}{2}</td><td>{3}</td><td>{4:?}</td><td>{5}</td><td>{6}</td>
+    <td><form action="/admin/task/{0}/cancel" method="post"><button type="submit">Cancel</button></form>{7}</td>
+</tr>"#,
+                        task_id,
+                        task.user_id,
+                        student_prompt,
+                        task.preset,
+                        task.status,
+                        position,
+                        wait_time,
+                        front_prompt
+                    );
+                }
+                let mode = admin_app.status.read().await.mode;
+                let mode_options: String = [
+                    DispatchMode::Open,
+                    DispatchMode::Paused,
+                    DispatchMode::Draining,
+                    DispatchMode::Closed,
+                ]
+                .into_iter()
+                .map(|option| {
+                    format!(
+                        r#"<option value="{0}"{1}>{0}</option>"#,
+                        option,
+                        if option == mode {
+                            r#" selected="selected""#
+                        } else {
+                            ""
+                        }
+                    )
+                })
+                .collect();
+                reply::html(format!(
+                    r#"
+{}
+<p>System status: {} Admin: {}</p>
+<form action="/admin/mode" method="post">
+    <label for="mode">Dispatch mode:</label>
+    <select name="mode" id="mode">{}</select>
+    <button type="submit">Set</button>
+</form>
+<form action="/admin/roster" method="post">
+    Roster: {}
+    <button type="submit">Reload roster</button>
+</form>
+<table id="admin-tasks">
+    <tr><th>Task</th><th>User</th><th>Preset</th><th>Status</th><th>Position</th><th>Expected / maximum wait</th><th></th></tr>
+    {}
+</table>
+<ul>
+    <li>A task moved to front is dispatched before any other pending task,
+    regardless of scheduling policy.</li>
+    <li>Paused accepts submissions and lets running tasks finish, but starts
+    nothing new. Draining is the same, and becomes paused once no task is
+    running, so it is safe to redeploy.</li>
+    <li>Closed rejects new submissions, but keeps running the queued ones.</li>
+</ul>
+"#,
+                    home_prompt(),
+                    admin_app.status.read().await,
+                    user_id,
+                    mode_options,
+                    admin_oauth
+                        .roster_size()
+                        .await
+                        .map(|size| format!("{} student(s)", size))
+                        .unwrap_or_else(|| String::from("disabled, everyone can submit")),
+                    task_list
+                ))
+            }
+        }));
+
+    let admin_app = app.clone();
+    let route = route.or(warp::path!("admin" / "task" / TaskId / String)
+        .and(warp::post())
+        .and(oauth.admin_id())
+        .and_then(move |task_id, action: String, user_id: String| {
+            let admin_app = admin_app.clone();
+            with_anyhow(async move {
+                let message = match &*action {
+                    "cancel" => {
+                        admin_app
+                            .cancel_task(task_id, Actor::Admin(user_id))
+                            .await?;
+                        "canceled"
+                    }
+                    "front" => {
+                        admin_app.move_to_front(task_id).await?;
+                        "moved to front"
+                    }
+                    _ => return Err(anyhow!("unknown action")),
+                };
+                Ok(reply::html(format!(
+                    r#"{}<p>Task #{} {}.</p><a href="/admin">Admin</a>"#,
+                    home_prompt(),
+                    task_id,
+                    message
+                )))
+            })
+        }));
+
+    let admin_oauth = oauth.clone();
+    let route = route.or(warp::path!("admin" / "roster")
+        .and(warp::post())
+        .and(oauth.admin_id())
+        .and_then(move |_| {
+            let admin_oauth = admin_oauth.clone();
+            with_anyhow(async move {
+                let size = admin_oauth.reload_roster().await?;
+                Ok(reply::html(format!(
+                    r#"{}<p>Roster reloaded, {} student(s).</p><a href="/admin">Admin</a>"#,
+                    home_prompt(),
+                    size
+                )))
+            })
+        }));
+
+    let admin_app = app.clone();
+    let route = route.or(warp::path!("admin" / "mode")
+        .and(warp::post())
+        .and(oauth.admin_id())
+        .and(warp::body::form())
+        .and_then(move |_, form: HashMap<String, String>| {
+            let admin_app = admin_app.clone();
+            with_anyhow(async move {
+                let mode: DispatchMode =
+                    form.get("mode").ok_or(anyhow!("no mode field"))?.parse()?;
+                admin_app.set_mode(mode).await;
+                Ok(reply::html(format!(
+                    r#"{}<p>Dispatch mode set to {}.</p><a href="/admin">Admin</a>"#,
+                    home_prompt(),
+                    mode
+                )))
+            })
+        }));
+
+    let route = route.or(api::routes(app.clone(), oauth.clone(), &config));
 
     let route = route.or(oauth.redirect(home_prompt()));
 
@@ -312,9 +957,9 @@ window.addEventListener('DOMContentLoaded', start);
         }));
 
     let login_prompt = format!(r#"{}<a href="{}">Login</a>"#, universal(), oauth.url);
-    let route = OAuth::recover(route, login_prompt);
-    warp::serve(route)
-        .run(([0, 0, 0, 0], env::var("CS5223FET_PORT")?.parse()?))
-        .await;
+    let route = OAuth::recover(route, login_prompt, |user_id: &str| {
+        format!("{}{}", home_prompt(), not_enrolled_prompt(user_id))
+    });
+    warp::serve(route).run(([0, 0, 0, 0], config.port)).await;
     Ok(())
 }
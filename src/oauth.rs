@@ -1,4 +1,8 @@
+use crate::config::Config;
+use crate::roster::{Roster, Student};
+use crate::store::TaskStore;
 use crate::with_anyhow;
+use anyhow::anyhow;
 use oauth2::basic::BasicClient;
 use oauth2::reqwest::async_http_client;
 use oauth2::url::Url;
@@ -6,44 +10,64 @@ use oauth2::{
     AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, TokenResponse,
     TokenUrl,
 };
-use serde_derive::Deserialize;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
-use std::env;
+use std::fmt::{self, Debug, Formatter};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, RwLock};
 use warp::reject;
 use warp::reject::{InvalidHeader, MissingCookie, Reject};
 use warp::reply;
 use warp::Filter;
 
-#[derive(Debug)]
 pub struct OAuth {
     pub url: Url,
     client: BasicClient,
     user_table: Mutex<HashMap<String, String>>,
+    token_table: Mutex<HashMap<String, ApiToken>>, // keyed by hash of token
+    store: Arc<dyn TaskStore>,
+    admins: Vec<String>,
+    roster: RwLock<Roster>,
 
     #[allow(unused)]
     csrf_token: CsrfToken, // TODO
 }
 
+// personal token for scripts and CLI, passed as `Authorization: Bearer ...`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub user_id: String,
+    pub name: String,
+}
+
+impl Debug for OAuth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuth").field("url", &self.url).finish()
+    }
+}
+
 impl OAuth {
-    pub fn new() -> anyhow::Result<Self> {
+    pub async fn new(config: &Config, store: Arc<dyn TaskStore>) -> anyhow::Result<Self> {
         let client = BasicClient::new(
-            ClientId::new(env::var("CS5223FET_CLIENT_ID")?),
-            Some(ClientSecret::new(env::var("CS5223FET_SECRET")?)),
+            ClientId::new(config.client_id.clone()),
+            Some(ClientSecret::new(config.secret.clone())),
             AuthUrl::new("https://github.com/login/oauth/authorize".to_string())?,
             Some(TokenUrl::new(
                 "https://github.com/login/oauth/access_token".to_string(),
             )?),
         )
-        .set_redirect_uri(RedirectUrl::new(format!(
-            "{}/redirect",
-            env::var("CS5223FET_URL")?
-        ))?);
+        .set_redirect_uri(RedirectUrl::new(format!("{}/redirect", config.url))?);
         let (auth_url, csrf_token) = client.authorize_url(CsrfToken::new_random).url();
         Ok(Self {
             client,
             user_table: Mutex::new(HashMap::new()),
+            token_table: Mutex::new(store.get_tokens().await?),
+            store,
+            admins: config.admins.clone(),
+            roster: RwLock::new(Roster::load(config)?),
             url: auth_url,
             csrf_token,
         })
@@ -54,6 +78,18 @@ impl OAuth {
 struct Expired;
 impl Reject for Expired {}
 
+#[derive(Debug)]
+struct InvalidToken;
+impl Reject for InvalidToken {}
+
+#[derive(Debug)]
+struct NotEnrolled(String);
+impl Reject for NotEnrolled {}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
 #[derive(Deserialize)]
 struct User {
     login: String,
@@ -63,8 +99,24 @@ impl OAuth {
     pub fn user_id(
         self: &Arc<Self>,
     ) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+        let bearer_oauth = self.clone();
+        let bearer = warp::header::<String>("authorization").and_then(move |value: String| {
+            let bearer_oauth = bearer_oauth.clone();
+            async move {
+                let token = value
+                    .strip_prefix("Bearer ")
+                    .ok_or_else(|| reject::custom(InvalidToken))?;
+                let token_table = bearer_oauth.token_table.lock().await;
+                if let Some(api_token) = token_table.get(&hash_token(token)) {
+                    Ok(api_token.user_id.clone())
+                } else {
+                    Err(reject::custom(InvalidToken))
+                }
+            }
+        });
+
         let oauth = self.clone();
-        warp::cookie::<String>("token").and_then(move |token| {
+        let cookie = warp::cookie::<String>("token").and_then(move |token| {
             let oauth = oauth.clone();
             async move {
                 let user_table = &oauth.user_table;
@@ -91,10 +143,135 @@ impl OAuth {
                     user_table.lock().await.insert(token, id.clone());
                     id
                 };
-                Ok(id)
+                Ok::<_, warp::Rejection>(id)
+            }
+        });
+        bearer.or(cookie).unify()
+    }
+
+    // `user_id` that is allowed to submit
+    pub fn student_id(
+        self: &Arc<Self>,
+    ) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+        let oauth = self.clone();
+        self.user_id().and_then(move |user_id: String| {
+            let oauth = oauth.clone();
+            async move {
+                if oauth.is_enrolled(&user_id).await {
+                    Ok(user_id)
+                } else {
+                    Err(reject::custom(NotEnrolled(user_id)))
+                }
             }
         })
     }
+
+    pub async fn is_enrolled(&self, user_id: &str) -> bool {
+        let roster = self.roster.read().await;
+        !roster.is_enabled() || self.is_admin(user_id) || roster.get(user_id).is_some()
+    }
+
+    // `None` if not in roster, or roster is disabled
+    pub async fn get_student(&self, user_id: &str) -> Option<Student> {
+        self.roster.read().await.get(user_id).cloned()
+    }
+
+    // number of students, `None` if roster is disabled
+    pub async fn roster_size(&self) -> Option<usize> {
+        let roster = self.roster.read().await;
+        Some(roster.len()).filter(|_| roster.is_enabled())
+    }
+
+    // number of students
+    pub async fn reload_roster(&self) -> anyhow::Result<usize> {
+        let mut roster = self.roster.write().await;
+        roster.reload()?;
+        println!("[oauth] roster reloaded, {} student(s)", roster.len());
+        Ok(roster.len())
+    }
+
+    // GitHub logins are case insensitive, as in `Roster::get`
+    pub fn is_admin(&self, user_id: &str) -> bool {
+        self.admins
+            .iter()
+            .any(|admin| admin.eq_ignore_ascii_case(user_id))
+    }
+
+    // `user_id` that is also an admin
+    pub fn admin_id(
+        self: &Arc<Self>,
+    ) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+        let oauth = self.clone();
+        self.user_id().and_then(move |user_id: String| {
+            let is_admin = oauth.is_admin(&user_id);
+            with_anyhow(async move {
+                if !is_admin {
+                    return Err(anyhow!("admin only"));
+                }
+                Ok(user_id)
+            })
+        })
+    }
+
+    // return the token, which is shown to user only once
+    pub async fn create_token(&self, user_id: &str, name: &str) -> anyhow::Result<String> {
+        if name.is_empty()
+            || name.len() > 40
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow!(
+                "token name should be 1 to 40 letters, digits, '-' or '_'"
+            ));
+        }
+        let mut token_table = self.token_table.lock().await;
+        if token_table
+            .values()
+            .any(|api_token| api_token.user_id == user_id && api_token.name == name)
+        {
+            return Err(anyhow!("token {:?} already exists", name));
+        }
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(40)
+            .map(char::from)
+            .collect();
+        let api_token = ApiToken {
+            user_id: user_id.to_string(),
+            name: name.to_string(),
+        };
+        let hash = hash_token(&token);
+        self.store.put_token(&hash, &api_token).await?;
+        token_table.insert(hash, api_token);
+        Ok(token)
+    }
+
+    pub async fn revoke_token(&self, user_id: &str, name: &str) -> anyhow::Result<()> {
+        let mut token_table = self.token_table.lock().await;
+        let hash = token_table
+            .iter()
+            .find(|(_, api_token)| api_token.user_id == user_id && api_token.name == name)
+            .map(|(hash, _)| hash.clone())
+            .ok_or(anyhow!("no token {:?}", name))?;
+        self.store.remove_token(&hash).await?;
+        token_table.remove(&hash);
+        Ok(())
+    }
+
+    // names of user's tokens, sorted
+    pub async fn token_list(&self, user_id: &str) -> Vec<String> {
+        let mut name_list: Vec<_> = self
+            .token_table
+            .lock()
+            .await
+            .values()
+            .filter(|api_token| api_token.user_id == user_id)
+            .map(|api_token| api_token.name.clone())
+            .collect();
+        name_list.sort_unstable();
+        name_list
+    }
 }
 
 #[derive(Deserialize)]
@@ -129,24 +306,160 @@ impl OAuth {
             })
     }
 
+    // `not_enrolled_prompt` renders page for the login not in roster, who is
+    // rejected by `student_id`
     pub fn recover(
         route: impl Clone + Filter<Extract = impl warp::Reply, Error = warp::Rejection>,
         login_prompt: String,
+        not_enrolled_prompt: impl Fn(&str) -> String + Clone + Send + Sync + 'static,
     ) -> impl Filter<Extract = impl warp::Reply> + Clone {
         route.recover(move |rejection: warp::Rejection| {
-            let login_prompt = Ok(reply::html(login_prompt.clone()));
+            let login_prompt = login_prompt.clone();
+            let not_enrolled_prompt = not_enrolled_prompt.clone();
             async move {
-                if let Some(Expired) = rejection.find() {
-                    return login_prompt;
+                if Self::is_unauthorized(&rejection) {
+                    return Ok(reply::html(login_prompt));
                 }
-                if let Some(_) = rejection.find::<InvalidHeader>() {
-                    return login_prompt;
-                }
-                if let Some(_) = rejection.find::<MissingCookie>() {
-                    return login_prompt;
+                if let Some(NotEnrolled(user_id)) = rejection.find() {
+                    return Ok(reply::html(not_enrolled_prompt(user_id)));
                 }
                 Err(rejection)
             }
         })
     }
+
+    pub fn is_not_enrolled(rejection: &warp::Rejection) -> bool {
+        rejection.find::<NotEnrolled>().is_some()
+    }
+
+    // whether `user_id` rejected because of missing or invalid credential
+    pub fn is_unauthorized(rejection: &warp::Rejection) -> bool {
+        rejection.find::<Expired>().is_some()
+            || rejection.find::<InvalidToken>().is_some()
+            || rejection.find::<InvalidHeader>().is_some()
+            || rejection.find::<MissingCookie>().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::App;
+    use crate::lab::Preset;
+    use crate::store::MemoryStore;
+    use warp::http::StatusCode;
+
+    async fn new_oauth(store: Arc<dyn TaskStore>) -> Arc<OAuth> {
+        let config = Config {
+            url: String::from("http://localhost"),
+            ..Config::default()
+        };
+        Arc::new(OAuth::new(&config, store).await.unwrap())
+    }
+
+    #[test]
+    fn sha256_hex() {
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[tokio::test]
+    async fn create_and_revoke() {
+        let store = Arc::new(MemoryStore::default());
+        let oauth = new_oauth(store.clone()).await;
+        let token = oauth.create_token("alice", "laptop").await.unwrap();
+        assert_eq!(token.len(), 40);
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric()));
+        // only hash is stored
+        let token_table = store.get_tokens().await.unwrap();
+        assert_eq!(token_table.len(), 1);
+        assert_eq!(token_table[&hash_token(&token)].user_id, "alice");
+
+        assert!(oauth.create_token("alice", "laptop").await.is_err());
+        for name in ["", "has space", &"x".repeat(41)] {
+            assert!(oauth.create_token("alice", name).await.is_err());
+        }
+        oauth.create_token("alice", "ci").await.unwrap();
+        oauth.create_token("bob", "laptop").await.unwrap();
+        assert_eq!(oauth.token_list("alice").await, ["ci", "laptop"]);
+
+        // tokens are loaded from store
+        let oauth = new_oauth(store.clone()).await;
+        let user_id = warp::test::request()
+            .header("authorization", format!("Bearer {}", token))
+            .filter(&oauth.user_id())
+            .await
+            .unwrap();
+        assert_eq!(user_id, "alice");
+
+        assert!(oauth.revoke_token("bob", "ci").await.is_err());
+        oauth.revoke_token("alice", "laptop").await.unwrap();
+        assert_eq!(oauth.token_list("alice").await, ["ci"]);
+        assert_eq!(oauth.token_list("bob").await, ["laptop"]);
+        assert!(!store
+            .get_tokens()
+            .await
+            .unwrap()
+            .contains_key(&hash_token(&token)));
+        let rejection = warp::test::request()
+            .header("authorization", format!("Bearer {}", token))
+            .filter(&oauth.user_id())
+            .await
+            .unwrap_err();
+        assert!(OAuth::is_unauthorized(&rejection));
+    }
+
+    #[tokio::test]
+    async fn bearer_over_cookie() {
+        let oauth = new_oauth(Arc::new(MemoryStore::default())).await;
+        let token = oauth.create_token("alice", "laptop").await.unwrap();
+        // logged in before, so no request to GitHub
+        oauth
+            .user_table
+            .lock()
+            .await
+            .insert(String::from("cookie"), String::from("bob"));
+
+        let user_id = warp::test::request()
+            .header("authorization", format!("Bearer {}", token))
+            .header("cookie", "token=cookie")
+            .filter(&oauth.user_id())
+            .await
+            .unwrap();
+        assert_eq!(user_id, "alice");
+        let user_id = warp::test::request()
+            .header("cookie", "token=cookie")
+            .filter(&oauth.user_id())
+            .await
+            .unwrap();
+        assert_eq!(user_id, "bob");
+    }
+
+    #[tokio::test]
+    async fn revoked_token_unauthorized() {
+        crate::presets::data::load(std::path::Path::new("labs")).unwrap();
+        let store = Arc::new(MemoryStore::default());
+        let config = Config {
+            url: String::from("http://localhost"),
+            ..Config::default()
+        };
+        let app = Arc::new(App::<Preset>::new(store.clone(), &config).await.unwrap());
+        let oauth = new_oauth(store).await;
+        let token = oauth.create_token("alice", "laptop").await.unwrap();
+        let route = crate::api::routes(app, oauth.clone(), &config);
+        let request = || {
+            warp::test::request()
+                .path("/api/v1/tasks")
+                .header("authorization", format!("Bearer {}", token))
+        };
+
+        assert_eq!(request().reply(&route).await.status(), StatusCode::OK);
+        oauth.revoke_token("alice", "laptop").await.unwrap();
+        assert_eq!(
+            request().reply(&route).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
 }
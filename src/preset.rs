@@ -1,4 +1,6 @@
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt::Display;
@@ -16,4 +18,14 @@ where
     fn render_html() -> String;
     fn get_command(&self) -> String;
     fn get_timeout(&self) -> u64;
+
+    // fields stored in the `task:{id}` hash
+    fn to_record(&self) -> Vec<(&'static str, String)> {
+        vec![("preset", to_string(self).unwrap())]
+    }
+    fn from_record(record: &HashMap<String, String>) -> anyhow::Result<Self> {
+        Ok(from_str(
+            record.get("preset").ok_or(anyhow!("no preset in record"))?,
+        )?)
+    }
 }
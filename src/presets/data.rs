@@ -0,0 +1,544 @@
+use anyhow::{anyhow, Context};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+// a lab described by a TOML file, see `labs/lab4.toml`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Lab {
+    pub name: String,  // short name, e.g. "Lab 4"
+    pub title: String, // shown above the submit form
+    pub command: String,
+    #[serde(default)]
+    pub timeout_margin: u64, // added to every timeout, for compile, collect output, etc.
+    pub log_levels: Vec<String>,
+    pub suites: Vec<Suite>,
+    pub parts: Vec<Part>,
+}
+
+// a selection of more than one test, submitted as `(part, 0)`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Suite {
+    pub name: String,
+    pub part: u32,
+    #[serde(default)]
+    pub args: String, // appended to `command`
+    pub timeout: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Part {
+    pub number: u32,
+    pub tests: Vec<Test>, // numbered from 1
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Test {
+    pub name: String,
+    #[serde(default)]
+    pub tags: Vec<String>, // tests tagged SEARCH are search tests, others are run tests
+    pub points: Option<u32>,
+    pub timeout: u64,
+}
+
+impl Test {
+    pub fn is_search(&self) -> bool {
+        self.tags.iter().any(|tag| tag == "SEARCH")
+    }
+}
+
+impl Lab {
+    pub fn get_suite(&self, part: u32, test: u32) -> Option<&Suite> {
+        if test != 0 {
+            return None;
+        }
+        self.suites.iter().find(|suite| suite.part == part)
+    }
+
+    pub fn get_test(&self, part: u32, test: u32) -> Option<&Test> {
+        self.parts
+            .iter()
+            .find(|p| p.number == part)?
+            .tests
+            .get((test as usize).checked_sub(1)?)
+    }
+
+    pub fn render_html(&self) -> String {
+        format!(
+            r#"
+<p>{}</p>
+<select name=":test">
+    {}
+    {}
+</select>
+<label for="log-level">Log level:</label>
+<select name=":log_level" id="log-level">
+    {}
+</select>
+<label for="check">Check:</label>
+<select name=":check" id="check">
+    <option value="yes">Yes</option>
+    <option value="no" selected="selected">No</option>
+</select>
+<ul>
+    <li>If you want to enable logging, you must run one specific run test.</li>
+    <li>If you want to enable checking, some of the running test must be search
+    test.</li>
+    <li>Enabling logging or checking will cause tests run differently compare to
+    they do during grading. Do not enable them unless you have a good reason.
+    </li>
+</ul>
+"#,
+            self.title,
+            self.suites
+                .iter()
+                .map(|suite| format!(
+                    r#"<option value="{}">{}</option>"#,
+                    to_string(&(suite.part, 0)).unwrap(),
+                    suite.name
+                ))
+                .collect::<Vec<_>>()
+                .join(""),
+            self.parts
+                .iter()
+                .flat_map(|part| (1..=part.tests.len() as u32).map(|test| (part.number, test)))
+                .enumerate()
+                .map(|(i, (part, test))| format!(
+                    r#"<option value="{}"{}>Part {} Test {}</option>"#,
+                    to_string(&(part, test)).unwrap(),
+                    if i == 0 {
+                        r#" selected="selected""#
+                    } else {
+                        ""
+                    },
+                    part,
+                    test
+                ))
+                .collect::<Vec<_>>()
+                .join(""),
+            ["disable"]
+                .into_iter()
+                .chain(self.log_levels.iter().map(|level| &**level))
+                .map(|level| format!(r#"<option value="{0}">{0}</option>"#, level))
+                .collect::<Vec<_>>()
+                .join(""),
+        )
+    }
+}
+
+static LAB_TABLE: RwLock<BTreeMap<String, Arc<Lab>>> = RwLock::new(BTreeMap::new());
+
+// every `<lab>.toml` in `dir`, must be called before any preset of them is
+// used, and may be called again, e.g., by every test, replacing labs of the
+// same name
+pub fn load(dir: &Path) -> anyhow::Result<()> {
+    let mut lab_table = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("cannot read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+            continue;
+        }
+        let lab = path.file_stem().unwrap().to_string_lossy().to_string();
+        let content = fs::read_to_string(&path)?;
+        let description: Lab = toml::from_str(&content)
+            .with_context(|| format!("invalid lab description {}", path.display()))?;
+        lab_table.push((lab, Arc::new(description)));
+    }
+    // all or nothing
+    LAB_TABLE.write().unwrap().extend(lab_table);
+    Ok(())
+}
+
+pub fn get_lab(lab: &str) -> Option<Arc<Lab>> {
+    LAB_TABLE.read().unwrap().get(lab).cloned()
+}
+
+// sorted
+pub fn lab_list() -> Vec<String> {
+    LAB_TABLE.read().unwrap().keys().cloned().collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Preset {
+    #[serde(skip)]
+    lab: String, // recorded separately
+    part: u32, // part 0 for all test
+    test: u32, // test 0 for a suite
+    log_level: LogLevel,
+    check: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum LogLevel {
+    Enable(String),
+    Disable,
+}
+
+impl Preset {
+    pub fn lab(&self) -> &str {
+        &self.lab
+    }
+
+    pub fn part(&self) -> u32 {
+        self.part
+    }
+
+    pub fn test(&self) -> u32 {
+        self.test
+    }
+
+    fn description(&self) -> Arc<Lab> {
+        // lab is checked on creating preset, and never unloaded
+        get_lab(&self.lab).unwrap()
+    }
+
+    pub fn from_json(lab: &str, preset: &str) -> anyhow::Result<Self> {
+        let description = get_lab(lab).ok_or(anyhow!("unknown lab {:?}", lab))?;
+        let preset = Self {
+            lab: lab.to_string(),
+            ..from_str(preset)?
+        };
+        if description.get_suite(preset.part, preset.test).is_none()
+            && description.get_test(preset.part, preset.test).is_none()
+        {
+            return Err(anyhow!(
+                "{} has no part {} test {}",
+                lab,
+                preset.part,
+                preset.test
+            ));
+        }
+        Ok(preset)
+    }
+
+    pub fn from_form(lab: &str, form: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let description = get_lab(lab).ok_or(anyhow!("unknown lab {:?}", lab))?;
+        let (part, test) = from_str(form.get(":test").ok_or(anyhow!("no test field"))?)?;
+        // `None` for a suite
+        let is_search = if description.get_suite(part, test).is_some() {
+            None
+        } else if let Some(test) = description.get_test(part, test) {
+            Some(test.is_search())
+        } else {
+            return Err(anyhow!("invalid part and test combination"));
+        };
+        let log_level = match &**form
+            .get(":log_level")
+            .ok_or(anyhow!("no log level field"))?
+        {
+            "disable" => LogLevel::Disable,
+            level if description.log_levels.iter().any(|l| l == level) => {
+                LogLevel::Enable(level.to_string())
+            }
+            _ => return Err(anyhow!("invalid log level")),
+        };
+        if is_search != Some(false) && log_level != LogLevel::Disable {
+            return Err(anyhow!("cannot enable logging for specified test"));
+        }
+        let check = match &**form.get(":check").ok_or(anyhow!("no check field"))? {
+            "yes" => true,
+            "no" => false,
+            _ => return Err(anyhow!("invalid check flag")),
+        };
+        if is_search == Some(false) && check {
+            return Err(anyhow!("cannot check specified test"));
+        }
+        Ok(Self {
+            lab: lab.to_string(),
+            part,
+            test,
+            log_level,
+            check,
+        })
+    }
+
+    pub fn get_command(&self) -> String {
+        let description = self.description();
+        format!(
+            r#"
+            tar -xf submit.tar.gz && 
+            find . -name "._*" | xargs -r rm &&
+            {} {} {} {}"#,
+            description.command,
+            if let Some(suite) = description.get_suite(self.part, self.test) {
+                suite.args.clone()
+            } else {
+                format!("--part {} --test {}", self.part, self.test)
+            },
+            match &self.log_level {
+                LogLevel::Disable => String::new(),
+                LogLevel::Enable(level) => format!("-g {}", level),
+            },
+            if self.check { "--checks" } else { "" }
+        )
+    }
+
+    pub fn get_timeout(&self) -> u64 {
+        let description = self.description();
+        description.timeout_margin
+            + if let Some(suite) = description.get_suite(self.part, self.test) {
+                suite.timeout
+            } else {
+                description.get_test(self.part, self.test).unwrap().timeout
+            }
+    }
+}
+
+impl Display for Preset {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let description = self.description();
+        if let Some(suite) = description.get_suite(self.part, self.test) {
+            write!(f, "{}", suite.name)?;
+        } else {
+            let test = description.get_test(self.part, self.test).unwrap();
+            write!(f, "TEST {}: {}", self.test, test.name)?;
+            for tag in &test.tags {
+                write!(f, " [{}]", tag)?;
+            }
+            if let Some(points) = test.points {
+                write!(f, " ({}pts)", points)?;
+            }
+        }
+        write!(f, ", log level: {:?}", self.log_level)?;
+        write!(f, "{}", if self.check { ", check" } else { "" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // expected values are those of the hand-written presets these files
+    // replaced, except lab 4 timeouts, which were indexed off by one
+    fn new_preset(
+        lab: &str,
+        part: u32,
+        test: u32,
+        log_level: &str,
+        check: bool,
+    ) -> anyhow::Result<Preset> {
+        load(Path::new("labs")).unwrap();
+        let form: HashMap<_, _> = [
+            (":test", to_string(&(part, test)).unwrap()),
+            (":log_level", log_level.to_string()),
+            (":check", String::from(if check { "yes" } else { "no" })),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+        Preset::from_form(lab, &form)
+    }
+
+    fn preset_of(lab: &str, part: u32, test: u32) -> Preset {
+        new_preset(lab, part, test, "disable", false).unwrap()
+    }
+
+    fn command_args(preset: &Preset) -> Vec<String> {
+        let command = preset.get_command();
+        let args = command.split("&&").last().unwrap();
+        args.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn lab4_suites() {
+        let preset = new_preset("lab4", 0, 0, "disable", true).unwrap();
+        assert_eq!(preset.to_string(), "All tests, log level: Disable, check");
+        assert_eq!(preset.get_timeout(), 1270);
+        assert_eq!(
+            command_args(&preset),
+            ["./run-tests.py", "--lab", "4", "--checks"]
+        );
+
+        let preset = preset_of("lab4", 4, 0);
+        assert_eq!(preset.to_string(), "All bonus tests, log level: Disable");
+        assert_eq!(preset.get_timeout(), 1270);
+        assert_eq!(
+            command_args(&preset),
+            ["./run-tests.py", "--lab", "4", "--part", "4"]
+        );
+    }
+
+    #[test]
+    fn lab4_tests() {
+        let preset = new_preset("lab4", 2, 1, "FINE", false).unwrap();
+        assert_eq!(
+            preset.to_string(),
+            r#"TEST 1: Single group, basic workload [RUN] (10pts), log level: Enable("FINE")"#
+        );
+        assert_eq!(
+            command_args(&preset),
+            [
+                "./run-tests.py",
+                "--lab",
+                "4",
+                "--part",
+                "2",
+                "--test",
+                "1",
+                "-g",
+                "FINE"
+            ]
+        );
+
+        for (part, test, timeout, name) in [
+            (1, 1, 5, "TEST 1: Commands return OK (5pts)"),
+            (1, 8, 5, "TEST 8: Application deterministic (10pts)"),
+            (
+                2,
+                1,
+                5,
+                "TEST 1: Single group, basic workload [RUN] (10pts)",
+            ),
+            (2, 2, 20, "TEST 2: Multi-group join/leave [RUN] (15pts)"),
+            (
+                2,
+                11,
+                20,
+                "TEST 11: One server per group random search [SEARCH] (20pts)",
+            ),
+            (
+                3,
+                5,
+                60,
+                "TEST 5: Repeated MultiPuts and MultiGets, different keys [RUN] (20pts)",
+            ),
+            (
+                4,
+                1,
+                5,
+                "TEST 1: Single group, basic workload [RUN] (10pts)",
+            ),
+            (
+                4,
+                14,
+                20,
+                "TEST 14: Multiple servers per group random search [SEARCH] (20pts)",
+            ),
+            (
+                4,
+                26,
+                20,
+                "TEST 26: Multiple servers per group random search [SEARCH] (20pts)",
+            ),
+        ] {
+            let preset = preset_of("lab4", part, test);
+            assert_eq!(
+                preset.get_timeout(),
+                5 + timeout,
+                "part {} test {}",
+                part,
+                test
+            );
+            assert_eq!(preset.to_string(), format!("{}, log level: Disable", name));
+        }
+        for (part, test) in [(1, 0), (1, 9), (2, 12), (4, 27), (5, 1)] {
+            assert!(new_preset("lab4", part, test, "disable", false).is_err());
+        }
+    }
+
+    #[test]
+    fn log_and_check_rules() {
+        // logging only for a single run test, and checks only for suites and
+        // search tests
+        for (lab, part, test, log, check) in [
+            ("lab4", 0, 0, false, true),
+            ("lab4", 4, 0, false, true),
+            ("lab4", 1, 3, true, false),
+            ("lab4", 2, 7, true, false),
+            ("lab4", 2, 8, false, true),
+            ("lab4", 4, 15, true, false),
+            ("lab4", 4, 22, false, true),
+            ("lab3", 0, 0, false, true),
+            ("lab3", 1, 19, true, false),
+            ("lab3", 1, 20, false, true),
+            ("lab3", 1, 27, false, true),
+        ] {
+            let case = format!("{} part {} test {}", lab, part, test);
+            assert!(
+                new_preset(lab, part, test, "disable", false).is_ok(),
+                "{}",
+                case
+            );
+            assert_eq!(
+                new_preset(lab, part, test, "INFO", false).is_ok(),
+                log,
+                "{}",
+                case
+            );
+            assert_eq!(
+                new_preset(lab, part, test, "disable", true).is_ok(),
+                check,
+                "{}",
+                case
+            );
+        }
+        assert!(new_preset("lab4", 1, 1, "VERBOSE", false).is_err());
+    }
+
+    #[test]
+    fn lab3() {
+        let preset = preset_of("lab3", 0, 0);
+        assert_eq!(preset.to_string(), "All tests, log level: Disable");
+        assert_eq!(preset.get_timeout(), 705);
+        assert_eq!(
+            command_args(&preset),
+            ["./run-tests.py", "--lab", "3", "--part", "1"]
+        );
+
+        for (test, timeout, name) in [
+            (1, 2, "TEST 1: Client throws InterruptedException [RUN]"),
+            (
+                19,
+                70,
+                "TEST 19: Constant repartitioning, full throughput [RUN] [UNRELIABLE]",
+            ),
+            (
+                22,
+                100,
+                "TEST 22: Two clients, sequential appends visible [SEARCH]",
+            ),
+            (
+                27,
+                20,
+                "TEST 27: Paxos runs in singleton group [RUN] [SEARCH]",
+            ),
+        ] {
+            let preset = preset_of("lab3", 1, test);
+            assert_eq!(preset.get_timeout(), 5 + timeout, "test {}", test);
+            assert_eq!(preset.to_string(), format!("{}, log level: Disable", name));
+            assert_eq!(
+                command_args(&preset),
+                [
+                    "./run-tests.py",
+                    "--lab",
+                    "3",
+                    "--part",
+                    "1",
+                    "--test",
+                    &test.to_string()
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let preset = new_preset("lab4", 2, 3, "FINER", false).unwrap();
+        let json = to_string(&preset).unwrap();
+        assert_eq!(
+            json,
+            r#"{"part":2,"test":3,"log_level":{"Enable":"FINER"},"check":false}"#
+        );
+        assert_eq!(Preset::from_json("lab4", &json).unwrap(), preset);
+        assert!(Preset::from_json("lab3", &json).is_err());
+        assert!(Preset::from_json("lab9", &json).is_err());
+    }
+}
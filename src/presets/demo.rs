@@ -45,14 +45,13 @@ impl PresetTrait for Preset {
     }
 
     fn render_html() -> String {
-        format!(
-            r#"
+        r#"
 <label for="duration">Sleep for:</label>
 <select name=":duration" id="duration">
     <option value="10">10 seconds</option>
     <option value="60">60 seconds</option>
 </select>        
 "#
-        )
+        .to_string()
     }
 }
@@ -0,0 +1,53 @@
+use crate::app::{TaskId, WorkerId};
+use serde_derive::{Deserialize, Serialize};
+
+// messages between server and worker over `/websocket`, each one a binary
+// frame of msgpack map tagged by "kind"
+//
+// a worker starts with `Hello`, then the server either answers `Welcome` or
+// closes the connection with a reason, e.g., for a different `VERSION` or a
+// wrong secret
+
+// bumped on any incompatible change below
+pub const VERSION: u32 = 1;
+
+// worker kills the running command on `ToWorker::Cancel`
+pub const CAPABILITY_CANCEL: &str = "cancel";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum ToWorker {
+    Welcome {
+        worker_id: WorkerId,
+    },
+    Run {
+        task_id: TaskId,
+        command: String,
+        upload: Vec<u8>,
+        timeout: u64, // in second
+    },
+    // kill running command, worker still reports partial output and finish
+    Cancel {
+        task_id: TaskId,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum FromWorker {
+    Hello {
+        version: u32,
+        secret: String, // `worker_secret` in server config
+        name: String,   // for logging only
+        capabilities: Vec<String>,
+    },
+    // appended to output as soon as received
+    Output {
+        task_id: TaskId,
+        output: String,
+    },
+    Finish {
+        task_id: TaskId,
+        exit_status: Option<i32>, // `None` if killed by signal
+    },
+}
@@ -0,0 +1,367 @@
+use crate::app::TaskId;
+use anyhow::anyhow;
+use serde_derive::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// pending task ids in dispatch order, the front is sent to the next idle worker
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaskQueue {
+    queue: VecDeque<TaskId>,
+    promoted: HashSet<TaskId>, // moved to front by staff, dispatched before any policy
+}
+
+impl TaskQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = TaskId> + '_ {
+        self.queue.iter().copied()
+    }
+
+    pub fn contains(&self, task_id: TaskId) -> bool {
+        self.queue.contains(&task_id)
+    }
+
+    pub fn position(&self, task_id: TaskId) -> Option<usize> {
+        self.queue.iter().position(|&id| id == task_id)
+    }
+
+    pub fn push(&mut self, task_id: TaskId) {
+        assert!(!self.contains(task_id));
+        self.queue.push_back(task_id);
+    }
+
+    pub fn push_front(&mut self, task_id: TaskId) {
+        assert!(!self.contains(task_id));
+        self.queue.push_front(task_id);
+    }
+
+    pub fn peek(&self) -> Option<TaskId> {
+        self.queue.front().copied()
+    }
+
+    pub fn pop(&mut self) -> Option<TaskId> {
+        let task_id = self.queue.pop_front()?;
+        self.promoted.remove(&task_id);
+        Some(task_id)
+    }
+
+    // return false if task is not queued
+    pub fn remove(&mut self, task_id: TaskId) -> bool {
+        if let Some(index) = self.position(task_id) {
+            self.queue.remove(index);
+            self.promoted.remove(&task_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn move_to_front(&mut self, task_id: TaskId) -> bool {
+        if self.remove(task_id) {
+            self.queue.push_front(task_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    // move to front and keep it ahead of tasks reordered by policy
+    pub fn promote(&mut self, task_id: TaskId) -> bool {
+        if self.move_to_front(task_id) {
+            self.promoted.insert(task_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    // stable, so tasks with equal key keep their relative order
+    pub fn reorder_by_key<K: Ord>(&mut self, key: impl FnMut(&TaskId) -> K) {
+        self.queue.make_contiguous().sort_by_key(key);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Policy {
+    Fifo,
+    FairShare, // least recent worker time first, FIFO within one user
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "fifo" => Ok(Self::Fifo),
+            "fair-share" => Ok(Self::FairShare),
+            _ => Err(anyhow!("unknown scheduling policy {:?}", s)),
+        }
+    }
+}
+
+// worker time spent on each user's tasks that started within the window
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Usage {
+    record_list: VecDeque<UsageRecord>, // in start order
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UsageRecord {
+    task_id: TaskId,
+    user_id: String,
+    start: Instant,
+    seconds: u64,
+}
+
+const USAGE_WINDOW: Duration = Duration::from_secs(3600);
+
+impl Usage {
+    fn expire(&mut self) {
+        while let Some(record) = self.record_list.front() {
+            if record.start.elapsed() < USAGE_WINDOW {
+                break;
+            }
+            self.record_list.pop_front();
+        }
+    }
+
+    // charge the expected run time when task starts
+    pub fn charge(&mut self, task_id: TaskId, user_id: &str, seconds: u64) {
+        self.expire();
+        self.record_list.push_back(UsageRecord {
+            task_id,
+            user_id: user_id.to_string(),
+            start: Instant::now(),
+            seconds,
+        });
+    }
+
+    // replace the charge with actual run time when task stops
+    pub fn settle(&mut self, task_id: TaskId) {
+        if let Some(record) = self
+            .record_list
+            .iter_mut()
+            .find(|record| record.task_id == task_id)
+        {
+            record.seconds = record.start.elapsed().as_secs();
+        }
+    }
+
+    pub fn get(&self, user_id: &str) -> u64 {
+        self.record_list
+            .iter()
+            .filter(|record| record.user_id == user_id && record.start.elapsed() < USAGE_WINDOW)
+            .map(|record| record.seconds)
+            .sum()
+    }
+}
+
+// actual run time of recent finished tasks of each preset, keyed by its
+// `Display`, so the wait time is estimated closer than by timeout
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunTime {
+    record_table: HashMap<String, VecDeque<u64>>,
+}
+
+const RUN_TIME_WINDOW: usize = 20;
+
+impl RunTime {
+    pub fn record(&mut self, preset: String, seconds: u64) {
+        let record_list = self.record_table.entry(preset).or_default();
+        if record_list.len() == RUN_TIME_WINDOW {
+            record_list.pop_front();
+        }
+        record_list.push_back(seconds);
+    }
+
+    // average of recent runs, `None` if never finished
+    pub fn expect(&self, preset: &str) -> Option<u64> {
+        let record_list = self.record_table.get(preset)?;
+        Some(record_list.iter().sum::<u64>() / record_list.len() as u64)
+    }
+}
+
+impl TaskQueue {
+    // the order tasks would be dispatched in if nothing else changes, `get_task`
+    // gives the user and expected run time of a queued task
+    pub fn plan<'a>(
+        &self,
+        policy: Policy,
+        usage: &Usage,
+        get_task: impl Fn(TaskId) -> (&'a str, u64),
+    ) -> Vec<TaskId> {
+        let (mut plan, rest): (Vec<_>, Vec<_>) = self
+            .iter()
+            .partition(|task_id| self.promoted.contains(task_id));
+        match policy {
+            Policy::Fifo => {
+                plan.extend(rest);
+                plan
+            }
+            Policy::FairShare => {
+                let mut user_usage = HashMap::new();
+                let mut rest = rest;
+                while !rest.is_empty() {
+                    // `min_by_key` keeps the first one on tie, which is the
+                    // earliest task of that user
+                    let (index, _) = rest
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, &task_id)| {
+                            let (user_id, _) = get_task(task_id);
+                            *user_usage
+                                .entry(user_id)
+                                .or_insert_with(|| usage.get(user_id))
+                        })
+                        .unwrap();
+                    let task_id = rest.remove(index);
+                    let (user_id, seconds) = get_task(task_id);
+                    *user_usage.get_mut(user_id).unwrap() += seconds;
+                    plan.push(task_id);
+                }
+                plan
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(task_list: &[TaskId]) -> TaskQueue {
+        let mut queue = TaskQueue::new();
+        for &task_id in task_list {
+            queue.push(task_id);
+        }
+        queue
+    }
+
+    #[test]
+    fn peek_and_pop() {
+        let mut queue = queue(&[1, 2]);
+        queue.push_front(3);
+        assert_eq!(queue.peek(), Some(3));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.peek(), Some(2));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.peek(), None);
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn remove_and_move_to_front() {
+        let mut queue = queue(&[1, 2, 3, 4]);
+        assert!(queue.remove(2));
+        assert!(!queue.remove(2));
+        assert!(!queue.contains(2));
+        assert!(queue.move_to_front(4));
+        assert!(!queue.move_to_front(5));
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![4, 1, 3]);
+        assert_eq!(queue.position(3), Some(2));
+        assert_eq!(queue.position(2), None);
+    }
+
+    #[test]
+    fn reorder_is_stable() {
+        let mut queue = queue(&[1, 2, 3, 4, 5]);
+        queue.reorder_by_key(|&task_id| task_id % 2 == 1);
+        assert_eq!(queue.iter().collect::<Vec<_>>(), vec![2, 4, 1, 3, 5]);
+    }
+
+    #[test]
+    fn pop_forgets_promotion() {
+        let mut queue = queue(&[1, 2]);
+        assert!(queue.promote(2));
+        assert_eq!(queue.pop(), Some(2));
+        queue.push(2);
+        let plan = queue.plan(Policy::Fifo, &Usage::default(), get_task);
+        assert_eq!(plan, vec![1, 2]);
+    }
+
+    // task 1-3 by alice, 4 by bob, 5 by carol, all take 60 seconds
+    fn get_task(task_id: TaskId) -> (&'static str, u64) {
+        let user_id = match task_id {
+            1..=3 => "alice",
+            4 => "bob",
+            _ => "carol",
+        };
+        (user_id, 60)
+    }
+
+    #[test]
+    fn fifo_plan() {
+        let queue = queue(&[1, 2, 3, 4, 5]);
+        let plan = queue.plan(Policy::Fifo, &Usage::default(), get_task);
+        assert_eq!(plan, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn fair_share_yields_to_other_users() {
+        let queue = queue(&[1, 2, 3, 4, 5]);
+        let plan = queue.plan(Policy::FairShare, &Usage::default(), get_task);
+        assert_eq!(plan, vec![1, 4, 5, 2, 3]);
+    }
+
+    #[test]
+    fn fair_share_counts_recent_usage() {
+        let queue = queue(&[1, 2, 4, 5]);
+        let mut usage = Usage::default();
+        usage.charge(0, "bob", 90);
+        let plan = queue.plan(Policy::FairShare, &usage, get_task);
+        assert_eq!(plan, vec![1, 5, 2, 4]);
+    }
+
+    #[test]
+    fn promoted_before_policy() {
+        let mut queue = queue(&[1, 2, 3, 4, 5]);
+        assert!(queue.promote(3));
+        assert!(!queue.promote(6));
+        let plan = queue.plan(Policy::FairShare, &Usage::default(), get_task);
+        assert_eq!(plan, vec![3, 1, 4, 5, 2]);
+    }
+
+    #[test]
+    fn run_time_average() {
+        let mut run_time = RunTime::default();
+        assert_eq!(run_time.expect("lab4"), None);
+        run_time.record(String::from("lab4"), 10);
+        run_time.record(String::from("lab4"), 21);
+        run_time.record(String::from("lab3"), 100);
+        // rounded down
+        assert_eq!(run_time.expect("lab4"), Some(15));
+        assert_eq!(run_time.expect("lab3"), Some(100));
+    }
+
+    #[test]
+    fn run_time_window() {
+        let mut run_time = RunTime::default();
+        run_time.record(String::from("lab4"), 1000);
+        for _ in 1..RUN_TIME_WINDOW {
+            run_time.record(String::from("lab4"), 10);
+        }
+        let window = RUN_TIME_WINDOW as u64;
+        assert_eq!(
+            run_time.expect("lab4"),
+            Some((1000 + (window - 1) * 10) / window)
+        );
+        // the oldest run drops out
+        run_time.record(String::from("lab4"), 10);
+        assert_eq!(run_time.expect("lab4"), Some(10));
+    }
+}
@@ -0,0 +1,357 @@
+use crate::app::TaskId;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// per-test results parsed from output of `run-tests.py`, which prints for
+// every test something like
+//
+//     TEST 1.2: Initial query returns NO_CONFIG [RUN] (5pts)
+//
+//     ...PASS (0.123s)
+//
+// followed by error details on failure
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestResult {
+    pub test: String, // e.g. "1.2", or "2" if printed without part
+    pub name: String,
+    pub outcome: Outcome,
+    pub points: u32, // earned
+    pub max_points: u32,
+    pub duration_ms: Option<u64>, // `None` if not reported
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    Pass,
+    Fail,
+    Timeout, // timed out by the test itself, or never finished
+}
+
+// number, name and points from "TEST 1.2: name [TAG] (5pts)"
+fn parse_header(line: &str) -> Option<(String, String, u32)> {
+    let (test, mut rest) = line.strip_prefix("TEST ")?.split_once(": ")?;
+    if test.is_empty() || !test.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    rest = rest.trim_end();
+    let mut max_points = 0;
+    if let Some(inner) = rest
+        .strip_suffix("pts)")
+        .and_then(|rest| rest.rsplit_once('('))
+    {
+        if let Ok(points) = inner.1.parse() {
+            max_points = points;
+            rest = inner.0.trim_end();
+        }
+    }
+    while let Some((name, tag)) = rest
+        .strip_suffix(']')
+        .and_then(|rest| rest.rsplit_once('['))
+    {
+        if tag.is_empty() || tag.contains(' ') {
+            break;
+        }
+        rest = name.trim_end();
+    }
+    Some((test.to_string(), rest.to_string(), max_points))
+}
+
+// pass or not and duration from "...PASS (0.123s)"
+fn parse_outcome(line: &str) -> Option<(bool, Option<u64>)> {
+    let rest = line.trim().strip_prefix("...")?;
+    let (pass, rest) = if let Some(rest) = rest.strip_prefix("PASS") {
+        (true, rest)
+    } else {
+        (false, rest.strip_prefix("FAIL")?)
+    };
+    let duration_ms = rest
+        .trim()
+        .strip_prefix('(')
+        .and_then(|rest| rest.strip_suffix("s)"))
+        .and_then(|seconds| seconds.parse::<f64>().ok())
+        .map(|seconds| (seconds * 1000.0).round() as u64);
+    Some((pass, duration_ms))
+}
+
+pub fn parse(output: &str) -> Vec<TestResult> {
+    let mut results: Vec<TestResult> = Vec::new();
+    let mut reported = true; // whether the last test has an outcome line
+    for line in output.lines() {
+        if let Some((test, name, max_points)) = parse_header(line) {
+            results.push(TestResult {
+                test,
+                name,
+                outcome: Outcome::Timeout,
+                points: 0,
+                max_points,
+                duration_ms: None,
+            });
+            reported = false;
+            continue;
+        }
+        let result = match results.last_mut() {
+            Some(result) => result,
+            None => continue,
+        };
+        if !reported {
+            if let Some((pass, duration_ms)) = parse_outcome(line) {
+                result.outcome = if pass { Outcome::Pass } else { Outcome::Fail };
+                result.points = if pass { result.max_points } else { 0 };
+                result.duration_ms = duration_ms;
+                reported = true;
+            }
+        } else if result.outcome == Outcome::Fail && line.to_lowercase().contains("timed out") {
+            // error details of the failed test
+            result.outcome = Outcome::Timeout;
+        }
+    }
+    results
+}
+
+// earned and possible points
+pub fn score(results: &[TestResult]) -> (u32, u32) {
+    results.iter().fold((0, 0), |(points, max_points), result| {
+        (points + result.points, max_points + result.max_points)
+    })
+}
+
+pub fn render_html(results: &[TestResult]) -> String {
+    if results.is_empty() {
+        return String::new();
+    }
+    let (points, max_points) = score(results);
+    format!(
+        r#"
+<table id="task-results">
+    <tr><th>Test</th><th>Name</th><th>Result</th><th>Points</th><th>Time</th></tr>
+    {}
+</table>
+<p>Total score: {}/{}</p>
+"#,
+        results
+            .iter()
+            .map(|result| format!(
+                "<tr><td>{}</td><td>{}</td><td>{:?}</td><td>{}/{}</td><td>{}</td></tr>",
+                result.test,
+                html_escape(&result.name),
+                result.outcome,
+                result.points,
+                result.max_points,
+                result
+                    .duration_ms
+                    .map(|duration_ms| format!("{:.3}s", duration_ms as f64 / 1000.0))
+                    .unwrap_or_default()
+            ))
+            .collect::<Vec<_>>()
+            .join(""),
+        points,
+        max_points
+    )
+}
+
+// runs in a pass rate, so an old failure does not count forever
+const TREND_WINDOW: usize = 5;
+
+// for "1.2" and "2" alike, so "1.10" comes after "1.9"
+fn test_key(test: &str) -> Vec<u32> {
+    test.split('.').map(|n| n.parse().unwrap_or(0)).collect()
+}
+
+// results of every test across tasks, in the order of `task_list`, as pass
+// rate over the last few runs
+pub fn render_trend_html(task_list: &[(TaskId, &[TestResult])]) -> String {
+    let mut test_table: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for &(task_id, results) in task_list {
+        for result in results {
+            test_table
+                .entry((test_key(&result.test), &result.test, &result.name))
+                .or_default()
+                .push((task_id, result.outcome));
+        }
+    }
+    if test_table.is_empty() {
+        return String::new();
+    }
+    let rows: String = test_table
+        .iter()
+        .map(|((_, test, name), runs)| {
+            let passed = |runs: &[(TaskId, Outcome)]| {
+                runs.iter()
+                    .filter(|(_, outcome)| *outcome == Outcome::Pass)
+                    .count()
+            };
+            let rate: Vec<_> = (0..runs.len())
+                .map(|i| {
+                    let window = &runs[(i + 1).saturating_sub(TREND_WINDOW)..=i];
+                    passed(window) as f64 / window.len() as f64
+                })
+                .collect();
+            let (x, y) = (|i: usize| 5 + i * 12, |rate: f64| 45.0 - rate * 40.0);
+            let line: Vec<_> = rate
+                .iter()
+                .enumerate()
+                .map(|(i, &rate)| format!("{},{:.1}", x(i), y(rate)))
+                .collect();
+            let dots: String = runs
+                .iter()
+                .zip(&rate)
+                .enumerate()
+                .map(|(i, ((task_id, outcome), &rate))| {
+                    let color = match outcome {
+                        Outcome::Pass => "green",
+                        Outcome::Fail => "red",
+                        Outcome::Timeout => "orange",
+                    };
+                    format!(
+                        r#"<circle cx="{}" cy="{:.1}" r="3" fill="{}"><title>#{} {:?}</title></circle>"#,
+                        x(i),
+                        y(rate),
+                        color,
+                        task_id,
+                        outcome
+                    )
+                })
+                .collect();
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td>{}/{}</td><td>{}/{}</td><td><svg width="{}" height="50"><polyline points="{}" fill="none" stroke="gray"/>{}</svg></td></tr>"#,
+                test,
+                html_escape(name),
+                passed(runs),
+                runs.len(),
+                passed(&runs[runs.len().saturating_sub(TREND_WINDOW)..]),
+                runs.len().min(TREND_WINDOW),
+                x(runs.len()),
+                line.join(" "),
+                dots
+            )
+        })
+        .collect();
+    format!(
+        r#"
+<table id="trends">
+    <tr><th>Test</th><th>Name</th><th>Passed</th><th>Last {}</th><th>Pass rate of last {} runs, oldest first</th></tr>
+    {}
+</table>
+"#,
+        TREND_WINDOW, TREND_WINDOW, rows
+    )
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: &str = "\
+--------------------------------------------------
+TEST 1.1: Single client basic operations [RUN] (5pts)
+
+...PASS (0.123s)
+--------------------------------------------------
+TEST 1.2: Multi-client partitioned [RUN] [UNRELIABLE] (10pts)
+
+java.lang.AssertionError: expected value 3
+...FAIL (2.5s)
+--------------------------------------------------
+TEST 1.3: Progress with one server down [SEARCH] (15pts)
+
+...FAIL (30.001s)
+Test timed out after 30s
+--------------------------------------------------
+TEST 2: Pings [RUN]
+
+...PASS
+--------------------------------------------------
+TEST 3: Long running workload [RUN] (20pts)
+
+";
+
+    fn result(results: &[TestResult], test: &str) -> TestResult {
+        results
+            .iter()
+            .find(|result| result.test == test)
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn pass_and_fail_with_duration() {
+        let results = parse(OUTPUT);
+        assert_eq!(
+            result(&results, "1.1"),
+            TestResult {
+                test: String::from("1.1"),
+                name: String::from("Single client basic operations"),
+                outcome: Outcome::Pass,
+                points: 5,
+                max_points: 5,
+                duration_ms: Some(123),
+            }
+        );
+        assert_eq!(
+            result(&results, "1.2"),
+            TestResult {
+                test: String::from("1.2"),
+                name: String::from("Multi-client partitioned"),
+                outcome: Outcome::Fail,
+                points: 0,
+                max_points: 10,
+                duration_ms: Some(2500),
+            }
+        );
+    }
+
+    #[test]
+    fn fail_then_timed_out() {
+        let result = result(&parse(OUTPUT), "1.3");
+        assert_eq!(result.outcome, Outcome::Timeout);
+        assert_eq!(result.duration_ms, Some(30001));
+        assert_eq!((result.points, result.max_points), (0, 15));
+    }
+
+    #[test]
+    fn no_outcome_line() {
+        let result = result(&parse(OUTPUT), "3");
+        assert_eq!(result.name, "Long running workload");
+        assert_eq!(result.outcome, Outcome::Timeout);
+        assert_eq!(result.duration_ms, None);
+    }
+
+    #[test]
+    fn header_without_part_or_points() {
+        let result = result(&parse(OUTPUT), "2");
+        assert_eq!(result.name, "Pings");
+        assert_eq!(result.outcome, Outcome::Pass);
+        assert_eq!((result.points, result.max_points), (0, 0));
+        assert_eq!(result.duration_ms, None);
+    }
+
+    #[test]
+    fn headers_and_score() {
+        let results = parse(OUTPUT);
+        let test_list: Vec<_> = results.iter().map(|result| &*result.test).collect();
+        assert_eq!(test_list, ["1.1", "1.2", "1.3", "2", "3"]);
+        assert_eq!(score(&results), (5, 50));
+    }
+
+    #[test]
+    fn not_a_header() {
+        assert_eq!(parse_header("TEST x.1: name (5pts)"), None);
+        assert_eq!(parse_header("TEST: name"), None);
+        assert_eq!(
+            parse_header("TEST 4.1: Name with [brackets inside] [RUN] (3pts)"),
+            Some((
+                String::from("4.1"),
+                String::from("Name with [brackets inside]"),
+                3
+            ))
+        );
+        assert!(parse("...PASS (1.0s)\n").is_empty());
+    }
+}
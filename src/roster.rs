@@ -0,0 +1,262 @@
+use crate::config::Config;
+use anyhow::{anyhow, Context};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+// GitHub logins allowed to submit, from `students` in config and the CSV file
+// at `roster`, disabled (everyone allowed) if neither is configured
+//
+// the CSV file has a header line, with a `github` column and optionally
+// `student_id` and `section` ones, e.g.
+//
+//     github,student_id,section
+//     alice,A0123456X,1
+//
+// blank lines are skipped, and every login must appear only once
+pub struct Roster {
+    students: Vec<String>,
+    path: Option<PathBuf>,
+    student_table: HashMap<String, Student>, // keyed by lowercase login
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Student {
+    pub student_id: String, // empty if not in roster file
+    pub section: String,
+}
+
+// fields of a CSV line, trimmed, a field may be quoted to contain `,` and
+// `""` for a quote, but not a line break
+fn split_fields(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut field_list = Vec::new();
+    let mut char_list = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        while char_list.next_if(|&c| c == ' ' || c == '\t').is_some() {}
+        if char_list.next_if_eq(&'"').is_some() {
+            loop {
+                match char_list.next() {
+                    Some('"') if char_list.next_if_eq(&'"').is_some() => field.push('"'),
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err(anyhow!("unterminated quote")),
+                }
+            }
+            while char_list.next_if(|&c| c == ' ' || c == '\t').is_some() {}
+            match char_list.next() {
+                None => {
+                    field_list.push(field);
+                    return Ok(field_list);
+                }
+                Some(',') => field_list.push(field),
+                Some(c) => return Err(anyhow!("unexpected {:?} after quoted field", c)),
+            }
+        } else {
+            loop {
+                match char_list.next() {
+                    None => {
+                        field_list.push(field.trim().to_string());
+                        return Ok(field_list);
+                    }
+                    Some(',') => break,
+                    Some('"') => return Err(anyhow!("quote in unquoted field")),
+                    Some(c) => field.push(c),
+                }
+            }
+            field_list.push(field.trim().to_string());
+        }
+    }
+}
+
+fn parse_csv(content: &str) -> anyhow::Result<HashMap<String, Student>> {
+    let mut line_list = content
+        .trim_start_matches('\u{feff}') // by spreadsheet export
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty());
+    let header: Vec<_> = split_fields(line_list.next().ok_or(anyhow!("no header line"))?.1)
+        .context("line 1")?
+        .into_iter()
+        .map(|column| column.to_lowercase())
+        .collect();
+    let index = |name| header.iter().position(|column| column == name);
+    let github = index("github").ok_or(anyhow!("no github column"))?;
+    let (student_id, section) = (index("student_id"), index("section"));
+
+    let mut student_table = HashMap::new();
+    for (number, line) in line_list {
+        let field_list = split_fields(line).with_context(|| format!("line {}", number))?;
+        let field = |index: Option<usize>| {
+            index
+                .and_then(|index| field_list.get(index))
+                .cloned()
+                .unwrap_or_default()
+        };
+        let login = field(Some(github));
+        if login.is_empty() {
+            return Err(anyhow!("line {}: no github login", number));
+        }
+        let student = Student {
+            student_id: field(student_id),
+            section: field(section),
+        };
+        if student_table
+            .insert(login.to_lowercase(), student)
+            .is_some()
+        {
+            return Err(anyhow!(
+                "line {}: duplicate github login {:?}",
+                number,
+                login
+            ));
+        }
+    }
+    Ok(student_table)
+}
+
+impl Roster {
+    pub fn load(config: &Config) -> anyhow::Result<Self> {
+        let mut roster = Self {
+            students: config.students.clone(),
+            path: Some(config.roster.clone()).filter(|path| !path.as_os_str().is_empty()),
+            student_table: HashMap::new(),
+        };
+        roster.reload()?;
+        Ok(roster)
+    }
+
+    // read roster file again, and keep the current roster on error
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        let mut student_table = if let Some(path) = &self.path {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("cannot read roster {}", path.display()))?;
+            parse_csv(&content).with_context(|| format!("invalid roster {}", path.display()))?
+        } else {
+            HashMap::new()
+        };
+        for login in &self.students {
+            student_table.entry(login.to_lowercase()).or_default();
+        }
+        self.student_table = student_table;
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.students.is_empty() || self.path.is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.student_table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.student_table.is_empty()
+    }
+
+    // GitHub logins are case insensitive
+    pub fn get(&self, login: &str) -> Option<&Student> {
+        self.student_table.get(&login.to_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header() {
+        let content = "Section , GitHub,Student_ID\n1,alice,A0123456X\n";
+        let student_table = parse_csv(content).unwrap();
+        let student = &student_table["alice"];
+        assert_eq!(student.student_id, "A0123456X");
+        assert_eq!(student.section, "1");
+
+        // only github column is required
+        let student_table = parse_csv("\u{feff}github\r\nAlice\r\n").unwrap();
+        assert_eq!(student_table["alice"].student_id, "");
+        assert!(parse_csv("login,student_id\nalice,A0123456X\n").is_err());
+        assert!(parse_csv("\n  \n").is_err());
+    }
+
+    #[test]
+    fn blank_lines() {
+        let content = "\ngithub,section\n\nalice,1\n   \nbob,2\n\n";
+        let student_table = parse_csv(content).unwrap();
+        assert_eq!(student_table.len(), 2);
+        assert_eq!(student_table["bob"].section, "2");
+    }
+
+    #[test]
+    fn quoted_fields() {
+        let content = concat!(
+            "\"github\",\"student_id\",section\n",
+            "\"alice\", \"A0123456X\" ,\"1, 2\"\n",
+            "bob,,\"\"\"lab\"\" group\"\n",
+        );
+        let student_table = parse_csv(content).unwrap();
+        assert_eq!(student_table["alice"].student_id, "A0123456X");
+        assert_eq!(student_table["alice"].section, "1, 2");
+        assert_eq!(student_table["bob"].section, "\"lab\" group");
+    }
+
+    #[test]
+    fn duplicate_rows() {
+        let content = "github,section\nalice,1\nbob,1\nAlice,2\n";
+        let error = parse_csv(content).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 4: duplicate github login \"Alice\""
+        );
+    }
+
+    #[test]
+    fn bad_rows() {
+        for (row, error) in [
+            (",A0123456X", "line 3: no github login"),
+            ("\"alice,A0123456X", "line 3"),
+            ("\"alice\"x,A0123456X", "line 3"),
+            ("al\"ice,A0123456X", "line 3"),
+        ] {
+            let content = format!("github,student_id\nbob,A0000000X\n{}\n", row);
+            assert_eq!(
+                parse_csv(&content).unwrap_err().to_string(),
+                error,
+                "{}",
+                row
+            );
+        }
+    }
+
+    #[test]
+    fn load_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roster.csv");
+        fs::write(&path, "github,student_id\nAlice,A0123456X\n").unwrap();
+        let config = Config {
+            students: vec![String::from("ta")],
+            roster: path.clone(),
+            ..Config::default()
+        };
+        let mut roster = Roster::load(&config).unwrap();
+        assert!(roster.is_enabled());
+        assert_eq!(roster.len(), 2);
+        assert_eq!(roster.get("ALICE").unwrap().student_id, "A0123456X");
+        assert_eq!(roster.get("TA").unwrap().student_id, "");
+        assert!(roster.get("bob").is_none());
+
+        // a bad file keeps the current roster
+        fs::write(&path, "github\nbob\nbob\n").unwrap();
+        assert!(roster.reload().is_err());
+        assert!(roster.get("alice").is_some());
+        fs::write(&path, "github\nbob\n").unwrap();
+        roster.reload().unwrap();
+        assert!(roster.get("alice").is_none());
+        assert!(roster.get("bob").is_some());
+
+        let roster = Roster::load(&Config::default()).unwrap();
+        assert!(!roster.is_enabled());
+        assert!(roster.is_empty());
+    }
+}
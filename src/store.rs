@@ -0,0 +1,528 @@
+use crate::app::TaskId;
+use crate::config::Config;
+use crate::oauth::ApiToken;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use redis::{AsyncCommands, Client};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{from_slice, from_str, to_string};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+// everything kept across restart: the `task:{id}` hash of each task, test
+// output of finished tasks, upload of unfinished tasks, and personal API tokens
+#[async_trait]
+pub trait TaskStore: Send + Sync {
+    // empty if task not exist
+    async fn get_task(&self, task_id: TaskId) -> anyhow::Result<HashMap<String, String>>;
+    async fn set_task(&self, task_id: TaskId, fields: &[(&str, String)]) -> anyhow::Result<()>;
+
+    async fn get_output(&self, task_id: TaskId) -> anyhow::Result<Option<Vec<u8>>>;
+    async fn put_output(&self, task_id: TaskId, output: &[u8]) -> anyhow::Result<()>;
+    // output must be put before
+    async fn append_output(&self, task_id: TaskId, output: &[u8]) -> anyhow::Result<()>;
+
+    async fn get_upload(&self, task_id: TaskId) -> anyhow::Result<Option<Vec<u8>>>;
+    async fn put_upload(&self, task_id: TaskId, upload: &[u8]) -> anyhow::Result<()>;
+    async fn remove_upload(&self, task_id: TaskId) -> anyhow::Result<()>;
+
+    // keyed by hash of token, the token itself is never stored
+    async fn get_tokens(&self) -> anyhow::Result<HashMap<String, ApiToken>>;
+    async fn put_token(&self, hash: &str, token: &ApiToken) -> anyhow::Result<()>;
+    async fn remove_token(&self, hash: &str) -> anyhow::Result<()>;
+}
+
+// "redis://..." for a redis server or "file:<path>" for an append-only log at
+// path, both plus files in `fs_dir`, or "memory" for local development
+pub async fn open(config: &Config) -> anyhow::Result<Arc<dyn TaskStore>> {
+    let url = &*config.store;
+    if url.starts_with("redis://") {
+        Ok(Arc::new(RedisStore::new(url, &config.fs_dir).await?))
+    } else if let Some(path) = url.strip_prefix("file:") {
+        Ok(Arc::new(FileStore::open(path, &config.fs_dir).await?))
+    } else if url == "memory" {
+        Ok(Arc::new(MemoryStore::default()))
+    } else {
+        Err(anyhow!("unknown store {:?}", url))
+    }
+}
+
+pub struct RedisStore {
+    client: Client,
+    output_dir: PathBuf,
+    upload_dir: PathBuf,
+}
+
+impl RedisStore {
+    pub async fn new(url: &str, fs_dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let client = Client::open(url)?;
+        client.get_async_connection().await?; // fail early if server is down
+        let fs_dir = fs_dir.into();
+        let output_dir = fs_dir.join("output");
+        let upload_dir = fs_dir.join("upload");
+        fs::create_dir_all(&output_dir).await?;
+        fs::create_dir_all(&upload_dir).await?;
+        Ok(Self {
+            client,
+            output_dir,
+            upload_dir,
+        })
+    }
+}
+
+async fn append_file(path: PathBuf, content: &[u8]) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new().append(true).open(path).await?;
+    file.write_all(content).await?;
+    // otherwise written in background, and may not be read right after
+    file.flush().await?;
+    Ok(())
+}
+
+async fn read_optional(path: PathBuf) -> anyhow::Result<Option<Vec<u8>>> {
+    match fs::read(path).await {
+        Ok(content) => Ok(Some(content)),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[async_trait]
+impl TaskStore for RedisStore {
+    async fn get_task(&self, task_id: TaskId) -> anyhow::Result<HashMap<String, String>> {
+        Ok(self
+            .client
+            .get_async_connection()
+            .await?
+            .hgetall(format!("task:{}", task_id))
+            .await?)
+    }
+
+    async fn set_task(&self, task_id: TaskId, fields: &[(&str, String)]) -> anyhow::Result<()> {
+        let _: () = self
+            .client
+            .get_async_connection()
+            .await?
+            .hset_multiple(format!("task:{}", task_id), fields)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_output(&self, task_id: TaskId) -> anyhow::Result<Option<Vec<u8>>> {
+        read_optional(self.output_dir.join(task_id.to_string())).await
+    }
+
+    async fn put_output(&self, task_id: TaskId, output: &[u8]) -> anyhow::Result<()> {
+        Ok(fs::write(self.output_dir.join(task_id.to_string()), output).await?)
+    }
+
+    async fn append_output(&self, task_id: TaskId, output: &[u8]) -> anyhow::Result<()> {
+        append_file(self.output_dir.join(task_id.to_string()), output).await
+    }
+
+    async fn get_upload(&self, task_id: TaskId) -> anyhow::Result<Option<Vec<u8>>> {
+        read_optional(self.upload_dir.join(task_id.to_string())).await
+    }
+
+    async fn put_upload(&self, task_id: TaskId, upload: &[u8]) -> anyhow::Result<()> {
+        Ok(fs::write(self.upload_dir.join(task_id.to_string()), upload).await?)
+    }
+
+    async fn remove_upload(&self, task_id: TaskId) -> anyhow::Result<()> {
+        match fs::remove_file(self.upload_dir.join(task_id.to_string())).await {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+    async fn get_tokens(&self) -> anyhow::Result<HashMap<String, ApiToken>> {
+        let token_table: HashMap<String, String> = self
+            .client
+            .get_async_connection()
+            .await?
+            .hgetall("api-tokens")
+            .await?;
+        token_table
+            .into_iter()
+            .map(|(hash, token)| Ok((hash, from_str(&token)?)))
+            .collect()
+    }
+
+    async fn put_token(&self, hash: &str, token: &ApiToken) -> anyhow::Result<()> {
+        let _: () = self
+            .client
+            .get_async_connection()
+            .await?
+            .hset("api-tokens", hash, to_string(token)?)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_token(&self, hash: &str) -> anyhow::Result<()> {
+        let _: () = self
+            .client
+            .get_async_connection()
+            .await?
+            .hdel("api-tokens", hash)
+            .await?;
+        Ok(())
+    }
+}
+
+// nothing survives restart, for testing and local development
+#[derive(Default)]
+pub struct MemoryStore {
+    task_table: Mutex<HashMap<TaskId, HashMap<String, String>>>,
+    output_table: Mutex<HashMap<TaskId, Vec<u8>>>,
+    upload_table: Mutex<HashMap<TaskId, Vec<u8>>>,
+    token_table: Mutex<HashMap<String, ApiToken>>,
+}
+
+#[async_trait]
+impl TaskStore for MemoryStore {
+    async fn get_task(&self, task_id: TaskId) -> anyhow::Result<HashMap<String, String>> {
+        Ok(self
+            .task_table
+            .lock()
+            .await
+            .get(&task_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_task(&self, task_id: TaskId, fields: &[(&str, String)]) -> anyhow::Result<()> {
+        let mut task_table = self.task_table.lock().await;
+        let task = task_table.entry(task_id).or_default();
+        for (field, value) in fields {
+            task.insert(field.to_string(), value.clone());
+        }
+        Ok(())
+    }
+
+    async fn get_output(&self, task_id: TaskId) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.output_table.lock().await.get(&task_id).cloned())
+    }
+
+    async fn put_output(&self, task_id: TaskId, output: &[u8]) -> anyhow::Result<()> {
+        self.output_table
+            .lock()
+            .await
+            .insert(task_id, output.to_vec());
+        Ok(())
+    }
+
+    async fn append_output(&self, task_id: TaskId, output: &[u8]) -> anyhow::Result<()> {
+        self.output_table
+            .lock()
+            .await
+            .get_mut(&task_id)
+            .ok_or(anyhow!("no output for task #{}", task_id))?
+            .extend_from_slice(output);
+        Ok(())
+    }
+
+    async fn get_upload(&self, task_id: TaskId) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.upload_table.lock().await.get(&task_id).cloned())
+    }
+
+    async fn put_upload(&self, task_id: TaskId, upload: &[u8]) -> anyhow::Result<()> {
+        self.upload_table
+            .lock()
+            .await
+            .insert(task_id, upload.to_vec());
+        Ok(())
+    }
+
+    async fn remove_upload(&self, task_id: TaskId) -> anyhow::Result<()> {
+        self.upload_table.lock().await.remove(&task_id);
+        Ok(())
+    }
+
+    async fn get_tokens(&self) -> anyhow::Result<HashMap<String, ApiToken>> {
+        Ok(self.token_table.lock().await.clone())
+    }
+
+    async fn put_token(&self, hash: &str, token: &ApiToken) -> anyhow::Result<()> {
+        self.token_table
+            .lock()
+            .await
+            .insert(hash.to_string(), token.clone());
+        Ok(())
+    }
+
+    async fn remove_token(&self, hash: &str) -> anyhow::Result<()> {
+        self.token_table.lock().await.remove(hash);
+        Ok(())
+    }
+}
+
+// single file alternative of redis, every change is appended to the log as a
+// line of JSON and the whole log is replayed into memory on open
+pub struct FileStore {
+    log: Mutex<File>,
+    task_table: Mutex<HashMap<TaskId, HashMap<String, String>>>,
+    output_table: Mutex<HashMap<TaskId, PathBuf>>,
+    token_table: Mutex<HashMap<String, ApiToken>>,
+    output_dir: PathBuf,
+    upload_dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum LogRecord {
+    Set {
+        task_id: TaskId,
+        fields: HashMap<String, String>,
+    },
+    Output {
+        task_id: TaskId,
+        path: PathBuf,
+    },
+    Token {
+        hash: String,
+        token: ApiToken,
+    },
+    RemoveToken {
+        hash: String,
+    },
+}
+
+impl FileStore {
+    pub async fn open(path: impl AsRef<Path>, fs_dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let fs_dir = fs_dir.into();
+        let output_dir = fs_dir.join("output");
+        let upload_dir = fs_dir.join("upload");
+        fs::create_dir_all(&output_dir).await?;
+        fs::create_dir_all(&upload_dir).await?;
+
+        let mut task_table: HashMap<_, HashMap<_, _>> = HashMap::new();
+        let mut output_table = HashMap::new();
+        let mut token_table = HashMap::new();
+        let content = match fs::read(path).await {
+            Ok(content) => content,
+            Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error.into()),
+        };
+        // a record is complete only with its newline, anything after the last
+        // one is cut off by a crash during appending, and may end in the middle
+        // of a UTF-8 sequence
+        let valid_length = content
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |index| index + 1);
+        if valid_length < content.len() {
+            println!("[store] warning: discard incomplete last line of log");
+        }
+        for (i, line) in content[..valid_length]
+            .split_inclusive(|&b| b == b'\n')
+            .enumerate()
+        {
+            let record = from_slice(line)
+                .map_err(|error| anyhow!("{}:{}: {}", path.display(), i + 1, error))?;
+            match record {
+                LogRecord::Set { task_id, fields } => {
+                    task_table.entry(task_id).or_default().extend(fields)
+                }
+                LogRecord::Output { task_id, path } => {
+                    output_table.insert(task_id, path);
+                }
+                LogRecord::Token { hash, token } => {
+                    token_table.insert(hash, token);
+                }
+                LogRecord::RemoveToken { hash } => {
+                    token_table.remove(&hash);
+                }
+            }
+        }
+
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        log.set_len(valid_length as _).await?;
+        Ok(Self {
+            log: Mutex::new(log),
+            task_table: Mutex::new(task_table),
+            output_table: Mutex::new(output_table),
+            token_table: Mutex::new(token_table),
+            output_dir,
+            upload_dir,
+        })
+    }
+
+    async fn append(&self, record: &LogRecord) -> anyhow::Result<()> {
+        let mut line = to_string(record)?;
+        line.push('\n');
+        let mut log = self.log.lock().await;
+        log.write_all(line.as_bytes()).await?;
+        log.sync_data().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TaskStore for FileStore {
+    async fn get_task(&self, task_id: TaskId) -> anyhow::Result<HashMap<String, String>> {
+        Ok(self
+            .task_table
+            .lock()
+            .await
+            .get(&task_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_task(&self, task_id: TaskId, fields: &[(&str, String)]) -> anyhow::Result<()> {
+        let fields: HashMap<_, _> = fields
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.clone()))
+            .collect();
+        let mut task_table = self.task_table.lock().await;
+        self.append(&LogRecord::Set {
+            task_id,
+            fields: fields.clone(),
+        })
+        .await?;
+        task_table.entry(task_id).or_default().extend(fields);
+        Ok(())
+    }
+
+    async fn get_output(&self, task_id: TaskId) -> anyhow::Result<Option<Vec<u8>>> {
+        let path = self.output_table.lock().await.get(&task_id).cloned();
+        if let Some(path) = path {
+            read_optional(path).await
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn put_output(&self, task_id: TaskId, output: &[u8]) -> anyhow::Result<()> {
+        let path = self.output_dir.join(task_id.to_string());
+        fs::write(&path, output).await?;
+        let mut output_table = self.output_table.lock().await;
+        self.append(&LogRecord::Output {
+            task_id,
+            path: path.clone(),
+        })
+        .await?;
+        output_table.insert(task_id, path);
+        Ok(())
+    }
+
+    async fn append_output(&self, task_id: TaskId, output: &[u8]) -> anyhow::Result<()> {
+        let path = self.output_table.lock().await.get(&task_id).cloned();
+        append_file(
+            path.ok_or(anyhow!("no output for task #{}", task_id))?,
+            output,
+        )
+        .await
+    }
+
+    async fn get_upload(&self, task_id: TaskId) -> anyhow::Result<Option<Vec<u8>>> {
+        read_optional(self.upload_dir.join(task_id.to_string())).await
+    }
+
+    async fn put_upload(&self, task_id: TaskId, upload: &[u8]) -> anyhow::Result<()> {
+        Ok(fs::write(self.upload_dir.join(task_id.to_string()), upload).await?)
+    }
+
+    async fn remove_upload(&self, task_id: TaskId) -> anyhow::Result<()> {
+        match fs::remove_file(self.upload_dir.join(task_id.to_string())).await {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn get_tokens(&self) -> anyhow::Result<HashMap<String, ApiToken>> {
+        Ok(self.token_table.lock().await.clone())
+    }
+
+    async fn put_token(&self, hash: &str, token: &ApiToken) -> anyhow::Result<()> {
+        let mut token_table = self.token_table.lock().await;
+        self.append(&LogRecord::Token {
+            hash: hash.to_string(),
+            token: token.clone(),
+        })
+        .await?;
+        token_table.insert(hash.to_string(), token.clone());
+        Ok(())
+    }
+
+    async fn remove_token(&self, hash: &str) -> anyhow::Result<()> {
+        let mut token_table = self.token_table.lock().await;
+        self.append(&LogRecord::RemoveToken {
+            hash: hash.to_string(),
+        })
+        .await?;
+        token_table.remove(hash);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replay_truncated_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let complete = concat!(
+            r#"{"kind":"set","task_id":1,"fields":{"user-id":"alice","status":"\"Pending\""}}"#,
+            "\n",
+            r#"{"kind":"set","task_id":1,"fields":{"status":"\"Finished\""}}"#,
+            "\n",
+        );
+        // a complete record missing only its newline, then one cut in the
+        // middle of "é"
+        let unterminated = r#"{"kind":"set","task_id":2,"fields":{"user-id":"bob"}}"#;
+        let cut = r#"{"kind":"set","task_id":2,"fields":{"user-id":"é"#;
+        for tail in [unterminated.as_bytes(), &cut.as_bytes()[..cut.len() - 1]] {
+            let mut content = complete.as_bytes().to_vec();
+            content.extend_from_slice(tail);
+            std::fs::write(&path, content).unwrap();
+
+            let store = FileStore::open(&path, dir.path()).await.unwrap();
+            let task = store.get_task(1).await.unwrap();
+            assert_eq!(task["user-id"], "alice");
+            assert_eq!(task["status"], "\"Finished\"");
+            assert!(store.get_task(2).await.unwrap().is_empty());
+            assert_eq!(std::fs::read(&path).unwrap(), complete.as_bytes());
+
+            // appended after the discarded tail, so replayed on next open
+            store
+                .set_task(2, &[("user-id", String::from("carol"))])
+                .await
+                .unwrap();
+            drop(store);
+            let store = FileStore::open(&path, dir.path()).await.unwrap();
+            assert_eq!(store.get_task(2).await.unwrap()["user-id"], "carol");
+        }
+    }
+
+    #[tokio::test]
+    async fn files_in_fs_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let fs_dir = dir.path().join("fs");
+        let store = FileStore::open(&path, &fs_dir).await.unwrap();
+        store.put_upload(1, b"upload").await.unwrap();
+        store.put_output(1, b"out").await.unwrap();
+        store.append_output(1, b"put").await.unwrap();
+        assert_eq!(std::fs::read(fs_dir.join("upload/1")).unwrap(), b"upload");
+        assert_eq!(std::fs::read(fs_dir.join("output/1")).unwrap(), b"output");
+        assert!(!dir.path().join("upload").exists());
+
+        drop(store);
+        let store = FileStore::open(&path, &fs_dir).await.unwrap();
+        assert_eq!(store.get_output(1).await.unwrap().unwrap(), b"output");
+        assert_eq!(store.get_upload(1).await.unwrap().unwrap(), b"upload");
+        store.remove_upload(1).await.unwrap();
+        assert_eq!(store.get_upload(1).await.unwrap(), None);
+    }
+}